@@ -0,0 +1,54 @@
+use core::cmp::Ordering;
+
+/// キューの中での要素の並び順を決める
+///
+/// `compare(a, b)` が `Ordering::Less` を返すとき、`a` は `b` より先に取り出される。
+pub trait Compare<T> {
+    fn compare(&self, a: &T, b: &T) -> Ordering;
+}
+
+/// 小さいものから取り出す(デフォルト)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MinOrder;
+
+/// 大きいものから取り出す
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MaxOrder;
+
+/// 要素から取り出したキーの小さいものから取り出す
+///
+/// 大きいものから取り出したい場合は `ByKey(|x| core::cmp::Reverse(x.key))` のようにする。
+#[derive(Debug, Clone, Copy)]
+pub struct ByKey<F>(pub F);
+
+impl<T: Ord> Compare<T> for MinOrder {
+    fn compare(&self, a: &T, b: &T) -> Ordering {
+        a.cmp(b)
+    }
+}
+
+impl<T: Ord> Compare<T> for MaxOrder {
+    fn compare(&self, a: &T, b: &T) -> Ordering {
+        b.cmp(a)
+    }
+}
+
+impl<T, K, F> Compare<T> for ByKey<F>
+where
+    K: Ord,
+    F: Fn(&T) -> K,
+{
+    fn compare(&self, a: &T, b: &T) -> Ordering {
+        (self.0)(a).cmp(&(self.0)(b))
+    }
+}
+
+/// 比較関数をそのまま使う
+impl<T, F> Compare<T> for F
+where
+    F: Fn(&T, &T) -> Ordering,
+{
+    fn compare(&self, a: &T, b: &T) -> Ordering {
+        self(a, b)
+    }
+}
@@ -0,0 +1,60 @@
+// 各ノードが D 個の子を持つヒープを配列に並べたときの位置の計算。子の数 D を選べるキューで共通に使う
+use core::ops::Range;
+
+// D が 2 未満ならコンパイル時にエラーにする。D 分木のキューを作るときに呼ぶ
+pub(crate) const fn assert_arity<const D: usize>() {
+    const { assert!(D >= 2, "a heap node needs at least two children") };
+}
+
+// i の親の位置。根なら None
+pub(crate) fn parent<const D: usize>(i: usize) -> Option<usize> {
+    if i == 0 {
+        None
+    } else {
+        Some((i - 1) / D)
+    }
+}
+
+// 要素が size 個のときの i の子の位置。子がいなければ(計算があふれる場合も) None
+pub(crate) fn children<const D: usize>(i: usize, size: usize) -> Option<Range<usize>> {
+    let first = i
+        .checked_mul(D)
+        .and_then(|c| c.checked_add(1))
+        .filter(|&c| c < size)?;
+    // first < size なので、あふれる場合も size に切り詰めればよい
+    Some(first..first.saturating_add(D).min(size))
+}
+
+// 要素が size 個のときの最初の葉の位置。これより前は子を持つ
+pub(crate) fn first_leaf<const D: usize>(size: usize) -> usize {
+    match size {
+        0 | 1 => 0,
+        size => (size - 2) / D + 1,
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_d_ary_positions() {
+    assert_eq!(parent::<2>(0), None);
+    assert_eq!(parent::<2>(6), Some(2));
+    assert_eq!(parent::<4>(4), Some(0));
+    assert_eq!(parent::<4>(5), Some(1));
+
+    assert_eq!(children::<2>(1, 10), Some(3..5));
+    assert_eq!(children::<4>(1, 7), Some(5..7));
+    assert_eq!(children::<4>(1, 5), None);
+    assert_eq!(
+        children::<2>(usize::MAX / 2 - 1, usize::MAX),
+        Some(usize::MAX - 2..usize::MAX)
+    );
+    assert_eq!(children::<2>(usize::MAX / 2, usize::MAX), None);
+    assert_eq!(children::<2>(usize::MAX, usize::MAX), None);
+
+    // 最初の葉の親は最後の要素の親の次
+    for size in 2..50 {
+        assert_eq!(first_leaf::<3>(size), parent::<3>(size - 1).unwrap() + 1);
+        assert!(children::<3>(first_leaf::<3>(size), size).is_none());
+    }
+    assert_eq!(first_leaf::<2>(1), 0);
+}
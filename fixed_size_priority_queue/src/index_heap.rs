@@ -0,0 +1,143 @@
+use crate::d_ary;
+
+/// 0..N の番号(slot)を D 分木のヒープの順に並べ、各 slot がヒープのどこにいるかを記録しておくヒープ
+///
+/// 要素そのものは持たず、比べるときは呼び出し側が slot 番号二つを受け取る `less` を渡す。
+/// `IndexedPriorityQueue` と `KeyedPriorityQueue` が、要素の置き場所とは別にヒープの並び順を持つのに使う。
+#[derive(Debug)]
+pub(crate) struct IndexHeap<const N: usize, const D: usize> {
+    // 0..N の並べ替えで、先頭 size 個がヒープ順に並んだ使用中の slot 番号、残りは使っていない slot 番号
+    heap: [usize; N],
+    // slot 番号ごとの heap 上の位置。heap の逆
+    positions: [usize; N],
+    size: usize,
+}
+
+impl<const N: usize, const D: usize> IndexHeap<N, D> {
+    pub(crate) fn new() -> Self {
+        d_ary::assert_arity::<D>();
+        IndexHeap {
+            heap: core::array::from_fn(|i| i),
+            positions: core::array::from_fn(|i| i),
+            size: 0,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.size
+    }
+
+    // 最も先に取り出される slot
+    pub(crate) fn head(&self) -> Option<usize> {
+        (self.size > 0).then(|| self.heap[0])
+    }
+
+    // 使っていない slot のうちの一つ。いっぱいなら None
+    pub(crate) fn vacant(&self) -> Option<usize> {
+        self.heap.get(self.size).copied()
+    }
+
+    // slot が使用中なら、その heap 上の位置
+    pub(crate) fn position(&self, slot: usize) -> Option<usize> {
+        self.positions.get(slot).copied().filter(|&i| i < self.size)
+    }
+
+    // 使っていない slot をヒープに加える。less は slot の要素を入れてから呼ぶこと
+    pub(crate) fn push(&mut self, slot: usize, less: impl Fn(usize, usize) -> bool) {
+        debug_assert!(self.position(slot).is_none());
+        self.swap(self.positions[slot], self.size);
+        self.size += 1;
+        self.sift_up(self.size - 1, &less);
+    }
+
+    // heap 上の i 番目の slot をヒープから外して返す。末尾の slot を i に持ってきて上下どちらかに動かす
+    pub(crate) fn remove_at(&mut self, i: usize, less: impl Fn(usize, usize) -> bool) -> usize {
+        let li = self.size - 1;
+        self.swap(i, li);
+        self.size -= 1;
+        if i < self.size {
+            self.sift_down(i, &less);
+            self.sift_up(i, &less);
+        }
+        self.heap[li]
+    }
+
+    // heap 上の i 番目の slot の要素が書き換わったので、上下どちらかに動かす
+    pub(crate) fn update(&mut self, i: usize, less: impl Fn(usize, usize) -> bool) {
+        self.sift_down(i, &less);
+        self.sift_up(i, &less);
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.size = 0;
+    }
+
+    fn swap(&mut self, i: usize, j: usize) {
+        self.heap.swap(i, j);
+        self.positions[self.heap[i]] = i;
+        self.positions[self.heap[j]] = j;
+    }
+
+    // heap 上の i 番目と j 番目の slot を比べる
+    fn less_at(&self, i: usize, j: usize, less: &impl Fn(usize, usize) -> bool) -> bool {
+        less(self.heap[i], self.heap[j])
+    }
+
+    fn sift_up(&mut self, mut i: usize, less: &impl Fn(usize, usize) -> bool) {
+        while let Some(pi) = d_ary::parent::<D>(i) {
+            if !self.less_at(i, pi, less) {
+                break;
+            }
+            self.swap(i, pi);
+            i = pi;
+        }
+    }
+
+    fn sift_down(&mut self, mut i: usize, less: &impl Fn(usize, usize) -> bool) {
+        while let Some(children) = d_ary::children::<D>(i, self.size) {
+            let smallest =
+                (children.start + 1..children.end).fold(children.start, |smallest, c| {
+                    if self.less_at(c, smallest, less) {
+                        c
+                    } else {
+                        smallest
+                    }
+                });
+            if !self.less_at(smallest, i, less) {
+                break;
+            }
+            self.swap(i, smallest);
+            i = smallest;
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_index_heap_positions() {
+    // slot ごとの値で並べ、出し入れしても heap と positions が互いの逆になっていることを確かめる
+    let values = [50, 20, 40, 10, 30, 60];
+    let less = |a: usize, b: usize| values[a] < values[b];
+    let mut heap = IndexHeap::<6, 2>::new();
+    for slot in [2, 0, 5, 3] {
+        heap.push(slot, less);
+    }
+    assert_eq!(heap.len(), 4);
+    assert_eq!(heap.head(), Some(3));
+    assert_eq!(heap.position(1), None);
+    assert!(matches!(heap.vacant(), Some(1 | 4)));
+
+    let i = heap.position(2).unwrap();
+    assert_eq!(heap.remove_at(i, less), 2);
+    heap.push(1, less);
+    for (i, &slot) in heap.heap.iter().enumerate() {
+        assert_eq!(heap.positions[slot], i);
+    }
+
+    let mut popped = Vec::new();
+    while heap.head().is_some() {
+        popped.push(heap.remove_at(0, less));
+    }
+    assert_eq!(popped, [3, 1, 0, 5]);
+    assert_eq!(heap.vacant(), Some(heap.heap[0]));
+}
@@ -0,0 +1,213 @@
+use crate::compare::{Compare, MinOrder};
+use crate::index_heap::IndexHeap;
+use core::cmp::Ordering;
+
+/// push 時に返すハンドルで、キューに入っている要素を後から書き換えたり取り除いたりできる優先度付きキュー
+///
+/// 要素の置き場所(slot)とヒープの並び順を分けて持ち、slot がヒープのどこにいるかを記録しておくことで、
+/// ハンドルから O(1) で要素を見つけ、O(log N) でヒープの条件を満たすように動かす。
+/// D はヒープの各ノードの子の数で、`FixedSizePriorityQueue` と同じく 2 以上を選べる。
+#[derive(Debug)]
+pub struct IndexedPriorityQueue<T, const N: usize, C = MinOrder, const D: usize = 2> {
+    slots: [Option<T>; N],
+    // slot が再利用されるたびに増やし、古いハンドルを無効にする
+    generations: [u32; N],
+    // 使用中の slot 番号をヒープ順に並べたもの
+    heap: IndexHeap<N, D>,
+    cmp: C,
+}
+
+/// `IndexedPriorityQueue::push` で返される、要素を指すハンドル
+///
+/// 要素が pop や remove で取り出された後は無効になり、同じ slot が再利用されても別の要素を指すことはない。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Handle {
+    slot: usize,
+    generation: u32,
+}
+
+impl<T, const N: usize, C, const D: usize> IndexedPriorityQueue<T, N, C, D>
+where
+    C: Compare<T>,
+{
+    pub fn new() -> Self
+    where
+        C: Default,
+    {
+        Self::with_comparator(C::default())
+    }
+
+    pub fn with_comparator(cmp: C) -> Self {
+        IndexedPriorityQueue {
+            slots: core::array::from_fn(|_| None),
+            generations: [0; N],
+            heap: IndexHeap::new(),
+            cmp,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.heap.len() == 0
+    }
+
+    pub fn peek(&self) -> Option<&T> {
+        self.heap.head().and_then(|slot| self.slots[slot].as_ref())
+    }
+
+    // 空いている slot に入れて末尾からヒープに追加する。いっぱいなら item をそのまま返す
+    pub fn push(&mut self, item: T) -> Result<Handle, T> {
+        let Some(slot) = self.heap.vacant() else {
+            return Err(item);
+        };
+        self.slots[slot] = Some(item);
+        let (slots, cmp) = (&self.slots, &self.cmp);
+        self.heap.push(slot, |a, b| Self::less(slots, cmp, a, b));
+        Ok(Handle {
+            slot,
+            generation: self.generations[slot],
+        })
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        if self.heap.len() > 0 {
+            Some(self.remove_at(0))
+        } else {
+            None
+        }
+    }
+
+    pub fn contains(&self, handle: Handle) -> bool {
+        self.position(handle).is_some()
+    }
+
+    pub fn get(&self, handle: Handle) -> Option<&T> {
+        self.position(handle)
+            .and_then(|_| self.slots[handle.slot].as_ref())
+    }
+
+    // ハンドルの要素を new_value に置き換えて、前の値を返す。ハンドルが無効なら new_value をそのまま返す
+    pub fn update(&mut self, handle: Handle, new_value: T) -> Result<T, T> {
+        match self.position(handle) {
+            Some(i) => {
+                let old = self.slots[handle.slot].replace(new_value).unwrap();
+                let (slots, cmp) = (&self.slots, &self.cmp);
+                self.heap.update(i, |a, b| Self::less(slots, cmp, a, b));
+                Ok(old)
+            }
+            None => Err(new_value),
+        }
+    }
+
+    pub fn remove(&mut self, handle: Handle) -> Option<T> {
+        self.position(handle).map(|i| self.remove_at(i))
+    }
+
+    // 有効なハンドルであれば、その要素の heap 上の位置を返す
+    fn position(&self, handle: Handle) -> Option<usize> {
+        self.heap
+            .position(handle.slot)
+            .filter(|_| self.generations[handle.slot] == handle.generation)
+    }
+
+    // heap 上の i 番目の要素を取り出す。取り出した slot は空き slot になる
+    fn remove_at(&mut self, i: usize) -> T {
+        let (slots, cmp) = (&self.slots, &self.cmp);
+        let slot = self.heap.remove_at(i, |a, b| Self::less(slots, cmp, a, b));
+        self.generations[slot] = self.generations[slot].wrapping_add(1);
+        self.slots[slot].take().unwrap()
+    }
+
+    // slot a の要素が slot b の要素より先に取り出されるべきか
+    fn less(slots: &[Option<T>; N], cmp: &C, a: usize, b: usize) -> bool {
+        let item = |slot: usize| slots[slot].as_ref().unwrap();
+        cmp.compare(item(a), item(b)) == Ordering::Less
+    }
+}
+
+impl<T, const N: usize, C, const D: usize> Default for IndexedPriorityQueue<T, N, C, D>
+where
+    C: Compare<T> + Default,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_indexed_update_and_remove() {
+    let mut queue = IndexedPriorityQueue::<u32, 4>::new();
+    let h10 = queue.push(10).unwrap();
+    let h20 = queue.push(20).unwrap();
+    let h30 = queue.push(30).unwrap();
+    let h40 = queue.push(40).unwrap();
+    assert_eq!(queue.push(50), Err(50));
+    assert_eq!(queue.peek(), Some(&10));
+
+    // 締め切りを早める / 遅らせる
+    assert_eq!(queue.update(h30, 5), Ok(30));
+    assert_eq!(queue.peek(), Some(&5));
+    assert_eq!(queue.update(h30, 35), Ok(5));
+    assert_eq!(queue.get(h30), Some(&35));
+    assert_eq!(queue.update(h10, 45), Ok(10));
+    assert_eq!(queue.peek(), Some(&20));
+
+    assert_eq!(queue.remove(h40), Some(40));
+    assert_eq!(queue.remove(h40), None);
+    assert!(!queue.contains(h40));
+    assert_eq!(queue.len(), 3);
+
+    assert_eq!(queue.pop(), Some(20));
+    assert_eq!(queue.pop(), Some(35));
+    assert_eq!(queue.pop(), Some(45));
+    assert_eq!(queue.pop(), None);
+    assert!(queue.is_empty());
+
+    // 取り出した後のハンドルは、slot が再利用されても無効のまま
+    let h = queue.push(1).unwrap();
+    for old in [h10, h20, h30, h40] {
+        assert_eq!(queue.get(old), None);
+        assert_eq!(queue.update(old, 0), Err(0));
+    }
+    assert_eq!(queue.get(h), Some(&1));
+}
+
+#[cfg(test)]
+#[test]
+fn test_indexed_keeps_heap_order() {
+    // 更新と削除を繰り返しても pop の順序が崩れないことを確認する
+    let mut queue = IndexedPriorityQueue::<u32, 16>::new();
+    let mut handles = [None; 16];
+    let mut expected = Vec::new();
+    let mut next = crate::xorshift(12345);
+
+    for (i, handle) in handles.iter_mut().enumerate() {
+        *handle = Some(queue.push(i as u32 * 7 % 16).unwrap());
+    }
+    for _ in 0..100 {
+        let i = (next() % 16) as usize;
+        let value = next() % 1000;
+        if let Some(handle) = handles[i] {
+            queue.update(handle, value).unwrap();
+        }
+    }
+    for handle in handles.iter().step_by(3).flatten() {
+        queue.remove(*handle).unwrap();
+    }
+    for handle in handles.iter().flatten() {
+        if let Some(&v) = queue.get(*handle) {
+            expected.push(v);
+        }
+    }
+    expected.sort();
+
+    let mut popped = Vec::new();
+    while let Some(v) = queue.pop() {
+        popped.push(v);
+    }
+    assert_eq!(popped, expected);
+}
@@ -0,0 +1,167 @@
+use crate::compare::{Compare, MinOrder};
+use crate::index_heap::IndexHeap;
+use core::cmp::Ordering;
+
+/// キー(0..K の整数)ごとに高々一つの要素を持つ優先度付きキュー
+///
+/// LED 番号ごとに次の締め切りを一つだけ持つような使い方を想定している。
+/// 同じキーで `insert_or_update` すると前の要素を置き換え、`remove` でキーを指定して取り除く(どちらも O(log K))。
+/// 並び順は優先度 P を C で比べて決める。D はヒープの各ノードの子の数。
+#[derive(Debug)]
+pub struct KeyedPriorityQueue<P, V, const K: usize, C = MinOrder, const D: usize = 2> {
+    entries: [Option<(P, V)>; K],
+    // 要素のあるキーをヒープ順に並べたもの
+    heap: IndexHeap<K, D>,
+    cmp: C,
+}
+
+impl<P, V, const K: usize, C, const D: usize> KeyedPriorityQueue<P, V, K, C, D>
+where
+    C: Compare<P>,
+{
+    pub fn new() -> Self
+    where
+        C: Default,
+    {
+        Self::with_comparator(C::default())
+    }
+
+    pub fn with_comparator(cmp: C) -> Self {
+        KeyedPriorityQueue {
+            entries: core::array::from_fn(|_| None),
+            heap: IndexHeap::new(),
+            cmp,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.heap.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        K
+    }
+
+    pub fn contains_key(&self, key: usize) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn get(&self, key: usize) -> Option<(&P, &V)> {
+        self.entries.get(key)?.as_ref().map(|(p, v)| (p, v))
+    }
+
+    // 最も先に取り出される要素とそのキー
+    pub fn peek(&self) -> Option<(usize, &P, &V)> {
+        let key = self.heap.head()?;
+        self.get(key).map(|(p, v)| (key, p, v))
+    }
+
+    pub fn pop(&mut self) -> Option<(usize, P, V)> {
+        let key = self.heap.head()?;
+        self.remove(key).map(|(p, v)| (key, p, v))
+    }
+
+    // key の要素を入れる。既にあれば置き換えて前の要素を返す。key が K 以上なら panic する
+    pub fn insert_or_update(&mut self, key: usize, priority: P, value: V) -> Option<(P, V)> {
+        assert!(key < K, "key {} is out of range 0..{}", key, K);
+        let old = self.entries[key].replace((priority, value));
+        let (entries, cmp) = (&self.entries, &self.cmp);
+        let less = |a, b| Self::less(entries, cmp, a, b);
+        match self.heap.position(key) {
+            Some(i) => self.heap.update(i, less),
+            None => self.heap.push(key, less),
+        }
+        old
+    }
+
+    pub fn remove(&mut self, key: usize) -> Option<(P, V)> {
+        let i = self.heap.position(key)?;
+        let (entries, cmp) = (&self.entries, &self.cmp);
+        self.heap
+            .remove_at(i, |a, b| Self::less(entries, cmp, a, b));
+        self.entries[key].take()
+    }
+
+    pub fn clear(&mut self) {
+        for entry in self.entries.iter_mut() {
+            *entry = None;
+        }
+        self.heap.clear();
+    }
+
+    // キー a の優先度がキー b の優先度より先に取り出されるべきか
+    fn less(entries: &[Option<(P, V)>; K], cmp: &C, a: usize, b: usize) -> bool {
+        let priority = |key: usize| &entries[key].as_ref().unwrap().0;
+        cmp.compare(priority(a), priority(b)) == Ordering::Less
+    }
+}
+
+impl<P, V, const K: usize, C, const D: usize> Default for KeyedPriorityQueue<P, V, K, C, D>
+where
+    C: Compare<P> + Default,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_keyed_one_entry_per_key() {
+    // LED 番号ごとに次に切り替える時刻と状態を一つだけ持つ
+    let mut queue = KeyedPriorityQueue::<u64, bool, 4>::new();
+    assert_eq!(queue.insert_or_update(0, 300, true), None);
+    assert_eq!(queue.insert_or_update(1, 100, false), None);
+    assert_eq!(queue.insert_or_update(2, 200, true), None);
+    assert_eq!(queue.len(), 3);
+    assert_eq!(queue.peek(), Some((1, &100, &false)));
+
+    // 新しい設定で前の締め切りを置き換える
+    assert_eq!(queue.insert_or_update(1, 400, true), Some((100, false)));
+    assert_eq!(queue.insert_or_update(0, 50, false), Some((300, true)));
+    assert_eq!(queue.len(), 3);
+    assert_eq!(queue.get(1), Some((&400, &true)));
+
+    assert_eq!(queue.remove(2), Some((200, true)));
+    assert_eq!(queue.remove(2), None);
+    assert_eq!(queue.remove(3), None);
+    assert_eq!(queue.remove(100), None);
+
+    assert_eq!(queue.pop(), Some((0, 50, false)));
+    assert_eq!(queue.pop(), Some((1, 400, true)));
+    assert_eq!(queue.pop(), None);
+    assert!(queue.is_empty());
+}
+
+#[cfg(test)]
+#[test]
+fn test_keyed_keeps_heap_order() {
+    // 更新と削除を繰り返しても、キーごとの最新の優先度の順に取り出せることを確認する
+    const K: usize = 16;
+    let mut queue = KeyedPriorityQueue::<u32, u32, K>::new();
+    let mut expected = [None; K];
+    let mut next = crate::xorshift(12345);
+
+    for round in 0..1000 {
+        let key = (next() % K as u32) as usize;
+        if next().is_multiple_of(4) {
+            assert_eq!(queue.remove(key).map(|(p, _)| p), expected[key].take());
+        } else {
+            let priority = next() % 1000;
+            assert_eq!(
+                queue.insert_or_update(key, priority, round).map(|(p, _)| p),
+                expected[key].replace(priority)
+            );
+        }
+        assert_eq!(queue.len(), expected.iter().flatten().count());
+    }
+
+    let mut sorted: Vec<u32> = expected.iter().flatten().copied().collect();
+    sorted.sort();
+    let popped: Vec<u32> = core::iter::from_fn(|| queue.pop().map(|(_, p, _)| p)).collect();
+    assert_eq!(popped, sorted);
+}
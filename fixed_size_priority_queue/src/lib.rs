@@ -1,193 +1,1178 @@
 #![cfg_attr(not(test), no_std)]
 
-use core::marker::Copy;
+mod compare;
+mod d_ary;
+mod index_heap;
+mod indexed;
+mod keyed;
+mod minmax;
+mod queue_ref;
+mod radix;
+#[cfg(feature = "serde")]
+mod serialize;
+mod spsc;
+mod stable;
+mod top_k;
 
+pub use compare::{ByKey, Compare, MaxOrder, MinOrder};
+pub use indexed::{Handle, IndexedPriorityQueue};
+pub use keyed::KeyedPriorityQueue;
+pub use minmax::MinMaxPriorityQueue;
+pub use queue_ref::PriorityQueueRef;
+pub use radix::{RadixHeap, RadixKey};
+#[cfg(feature = "serde")]
+pub use serialize::QueueSeed;
+pub use spsc::{Consumer, Producer, SpscPriorityQueue};
+pub use stable::StablePriorityQueue;
+pub use top_k::TopK;
+
+use core::cmp::Ordering;
+
+// 並び順は C で決める。デフォルトは小さいものから取り出す
+// D はヒープの各ノードの子の数。4 や 8 にすると木が浅くなり入れ替える回数は減るが、比べる回数が増えるので
+// 速くなるとは限らない。使い方に合わせて bench で確かめる
 #[derive(Debug, PartialEq)]
-pub struct FixedSizePriorityQueue<T, const N: usize> {
-    pub array: [Option<T>; N], // led_pins のテストのため。。
+pub struct FixedSizePriorityQueue<T, const N: usize, C = MinOrder, const D: usize = 2> {
+    array: [Option<T>; N],
     size: usize,
+    cmp: C,
+    overflow_policy: OverflowPolicy,
+    stats: QueueStats,
+}
+
+/// いっぱいのキューに push されたときに、どの要素を捨てるか
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum OverflowPolicy {
+    /// push された要素を捨てる
+    #[default]
+    RejectNewest,
+    /// 最も優先度の低い要素を捨てて push された要素を入れる。push された要素の方が優先度が低ければそちらを捨てる
+    EvictLowest,
+}
+
+/// キューの使われ方の統計。実際に動かしたときの値から N を決めるのに使う
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct QueueStats {
+    /// 同時に入っていた要素の数の最大値
+    pub high_water_mark: usize,
+    /// push された(extend で追加されたものも含む)要素の数
+    pub pushes: u32,
+    /// いっぱいのときに push されて捨てた要素の数。`dropped()` と同じ
+    pub dropped: u32,
 }
 
-impl<T, const N: usize> FixedSizePriorityQueue<T, N>
+impl QueueStats {
+    const fn new() -> Self {
+        QueueStats {
+            high_water_mark: 0,
+            pushes: 0,
+            dropped: 0,
+        }
+    }
+
+    // 要素の数が増えたときに呼ぶ
+    fn record_len(&mut self, len: usize) {
+        self.high_water_mark = self.high_water_mark.max(len);
+    }
+}
+
+impl<T, const N: usize, C, const D: usize> FixedSizePriorityQueue<T, N, C, D>
 where
-    T: Copy + Ord + Sized,
+    C: Compare<T>,
 {
-    pub fn new() -> Self {
+    pub fn new() -> Self
+    where
+        C: Default,
+    {
+        Self::with_comparator(C::default())
+    }
+
+    // const fn なので static の初期化にも使える
+    pub const fn with_comparator(cmp: C) -> Self {
+        d_ary::assert_arity::<D>();
         FixedSizePriorityQueue {
-            array: [None; N],
+            // T が Copy でなくても作れるように、要素ごとに None を作る
+            array: [const { None }; N],
             size: 0,
+            cmp,
+            overflow_policy: OverflowPolicy::RejectNewest,
+            stats: QueueStats::new(),
+        }
+    }
+
+    // 配列に並べてから葉に近い方から sift_down してヒープにする。一つずつ push するより速い(O(n))
+    pub fn from_array<const M: usize>(items: [T; M]) -> Self
+    where
+        C: Default,
+    {
+        Self::from_array_with_comparator(items, C::default())
+    }
+
+    // ByKey に関数ポインタを渡した場合のように C が Default でなくても、比較を渡してまとめて作る
+    pub fn from_array_with_comparator<const M: usize>(items: [T; M], cmp: C) -> Self {
+        const { assert!(M <= N, "too many items for the capacity") };
+        let mut queue = Self::with_comparator(cmp);
+        for (slot, item) in queue.array.iter_mut().zip(items) {
+            *slot = Some(item);
+        }
+        queue.size = M;
+        queue.stats.record_len(M);
+        queue.rebuild();
+        queue
+    }
+
+    // other の要素を全て移す。入りきらない場合は何もせずに other をそのまま返す
+    pub fn merge<const M: usize>(
+        &mut self,
+        mut other: FixedSizePriorityQueue<T, M, C, D>,
+    ) -> Result<(), FixedSizePriorityQueue<T, M, C, D>> {
+        if self.size + other.size > N {
+            return Err(other);
         }
+        self.extend(other.drain());
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        N
     }
 
     pub fn peek(&self) -> Option<&T> {
-        self.array.get(0).and_then(|opt| opt.as_ref())
+        (self.size > 0).then(|| self.item(0))
     }
 
-    // 先頭を取り出して、末尾を先頭に持って行って、先頭からmin_heapy
+    // 先頭の要素を書き換えるためのガードを返す。書き換えた場合はガードを drop したときに並べ直す
+    pub fn peek_mut(&mut self) -> Option<PeekMut<'_, T, N, C, D>> {
+        if self.size > 0 {
+            Some(PeekMut {
+                queue: self,
+                modified: false,
+            })
+        } else {
+            None
+        }
+    }
+
+    // ヒープ内の並び順(配列の順)で要素を見る。取り出される順とは限らない
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.array[..self.size].iter().flatten()
+    }
+
+    // 全ての要素をヒープ内の並び順で取り出す。途中で捨てても残りは取り除かれる
+    pub fn drain(&mut self) -> Drain<'_, T> {
+        let size = core::mem::replace(&mut self.size, 0);
+        Drain {
+            iter: self.array[..size].iter_mut(),
+        }
+    }
+
+    // 全ての要素を取り出される順(pop の順)で取り出す。途中で捨てても残りは取り除かれる
+    pub fn drain_sorted(&mut self) -> DrainSorted<'_, T, N, C, D> {
+        DrainSorted { queue: self }
+    }
+
+    // 取り出される順に前から詰めた配列にする
+    pub fn into_sorted_array(mut self) -> [Option<T>; N] {
+        let mut sorted: [Option<T>; N] = core::array::from_fn(|_| None);
+        for slot in sorted.iter_mut() {
+            *slot = self.pop();
+        }
+        sorted
+    }
+
+    // 末尾を先頭と入れ替えて先頭だったものを取り出し、先頭から sift_down
     pub fn pop(&mut self) -> Option<T> {
         if self.size > 0 {
-            let root = self.array[0].take().unwrap();
             let li = self.size - 1;
-            if let Some(_) = self.array[li] {
-                self.array.swap(0, li);
-                self.min_heapy(0);
-            }
+            self.array.swap(0, li);
+            let root = self.array[li].take();
             self.size -= 1;
-            Some(root)
+            if self.size > 0 {
+                self.sift_down(0);
+            }
+            root
         } else {
             None
         }
     }
 
-    pub fn push(&mut self, item: T) -> bool {
+    // いっぱいで追加できなかった場合は、overflow_policy に従って捨てた要素を Err で返す
+    pub fn push(&mut self, item: T) -> Result<(), T> {
+        self.stats.pushes = self.stats.pushes.saturating_add(1);
         if self.size < N {
             self.array[self.size] = Some(item);
-            self.heapify_up(self.size);
+            self.sift_up(self.size);
             self.size += 1;
-            true
+            self.stats.record_len(self.size);
+            Ok(())
         } else {
-            false // もう追加できない
+            self.stats.dropped = self.stats.dropped.saturating_add(1);
+            match self.overflow_policy {
+                OverflowPolicy::RejectNewest => Err(item),
+                OverflowPolicy::EvictLowest => match self.lowest() {
+                    Some(li) if self.cmp.compare(&item, self.item(li)) == Ordering::Less => {
+                        // 葉にあるので上に動かすだけでよい
+                        let evicted = self.array[li].replace(item).unwrap();
+                        self.sift_up(li);
+                        Err(evicted)
+                    }
+                    _ => Err(item),
+                },
+            }
         }
     }
 
-    // fn root() -> usize {
-    //     0
-    // }
-    fn parent(i: usize) -> Option<usize> {
-        if i == 0 {
-            None
-        } else {
-            Some((i + 1) / 2 - 1)
+    pub fn overflow_policy(&self) -> OverflowPolicy {
+        self.overflow_policy
+    }
+
+    pub fn set_overflow_policy(&mut self, overflow_policy: OverflowPolicy) {
+        self.overflow_policy = overflow_policy;
+    }
+
+    // いっぱいのときに push されて捨てた要素の数
+    pub fn dropped(&self) -> u32 {
+        self.stats.dropped
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        self.stats
+    }
+
+    // 統計を 0 に戻す。high_water_mark は今入っている要素の数から数え直す
+    pub fn reset_stats(&mut self) {
+        self.stats = QueueStats::new();
+        self.stats.record_len(self.size);
+    }
+
+    // 条件を満たさない要素を全て取り除く。残った要素を前に詰めてからヒープを作り直す
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&T) -> bool,
+    {
+        let mut kept = 0;
+        for i in 0..self.size {
+            let keep = f(self.item(i));
+            if keep {
+                self.array.swap(kept, i);
+                kept += 1;
+            } else {
+                self.array[i] = None;
+            }
         }
+        self.size = kept;
+        self.rebuild();
     }
-    fn left(i: usize) -> Option<usize> {
-        // FIXME: iが大きすぎる時にオーバーフロー
-        Some((i + 1) * 2 - 1).filter(|&i| i < N)
+
+    // 条件を満たす要素のうち、最も優先度の高いもの(popで先に出てくるもの)を一つ取り除いて返す
+    pub fn remove_first<F>(&mut self, mut f: F) -> Option<T>
+    where
+        F: FnMut(&T) -> bool,
+    {
+        let found = (0..self.size)
+            .filter(|&i| f(self.item(i)))
+            .reduce(|found, i| if self.less(i, found) { i } else { found })?;
+        Some(self.remove_at(found))
     }
-    fn right(i: usize) -> Option<usize> {
-        // FIXME: iが大きすぎる時にオーバーフロー
-        Some((i + 1) * 2).filter(|&i| i < N)
+
+    pub fn clear(&mut self) {
+        for slot in self.array.iter_mut() {
+            *slot = None;
+        }
+        self.size = 0;
     }
 
-    // インデックスで指定されたノードとその子ノードの間でヒープの条件を満たすようにする
-    fn min_heapy(&mut self, i: usize) {
-        let _ = self.array[i].unwrap();
+    // 指定した位置の要素を末尾と入れ替えて取り出し、入れ替えた要素を上下どちらかに動かす
+    fn remove_at(&mut self, i: usize) -> T {
+        let li = self.size - 1;
+        self.array.swap(i, li);
+        let removed = self.array[li].take().unwrap();
+        self.size -= 1;
+        if i < self.size {
+            self.sift_down(i);
+            self.sift_up(i);
+        }
+        removed
+    }
 
-        let smallest: usize = [Some(i), Self::left(i), Self::right(i)]
-            .iter()
-            .filter_map(|&option| option)
-            .flat_map(|i| self.array[i].map(|v| (v, i)))
-            .min_by(|x, y| x.0.cmp(&y.0))
-            .unwrap()
-            .1;
+    // ヒープ内の要素は必ず Some なので、参照を取り出す
+    fn item(&self, i: usize) -> &T {
+        self.array[i].as_ref().unwrap()
+    }
+
+    // 全ての要素が親より先に取り出されるべきでない(ヒープの条件を満たしている)か
+    #[cfg(feature = "serde")]
+    fn is_heap(&self) -> bool {
+        (1..self.size).all(|i| d_ary::parent::<D>(i).is_none_or(|pi| !self.less(i, pi)))
+    }
+
+    // 要素の並びを気にせず詰めてある状態から、ヒープの条件を満たすように並べ直す
+    fn rebuild(&mut self) {
+        for i in (0..d_ary::first_leaf::<D>(self.size)).rev() {
+            self.sift_down(i);
+        }
+    }
+
+    // 最も優先度の低い要素(最後に取り出されるもの)の位置。葉のどれかにある
+    fn lowest(&self) -> Option<usize> {
+        (d_ary::first_leaf::<D>(self.size)..self.size).reduce(|lowest, i| {
+            if self.less(lowest, i) {
+                i
+            } else {
+                lowest
+            }
+        })
+    }
 
-        if smallest != i {
+    // i の要素が j の要素より先に取り出されるべきか
+    fn less(&self, i: usize, j: usize) -> bool {
+        self.cmp.compare(self.item(i), self.item(j)) == Ordering::Less
+    }
+
+    // i の要素が子より後に取り出されるべきなら、最も先に取り出されるべき子と入れ替えて葉の方向に繰り返す
+    fn sift_down(&mut self, mut i: usize) {
+        while let Some(children) = d_ary::children::<D>(i, self.size) {
+            let smallest =
+                (children.start + 1..children.end).fold(children.start, |smallest, c| {
+                    if self.less(c, smallest) {
+                        c
+                    } else {
+                        smallest
+                    }
+                });
+            if !self.less(smallest, i) {
+                break;
+            }
             self.array.swap(i, smallest);
-            self.min_heapy(smallest)
+            i = smallest;
+        }
+    }
+
+    // i の要素が親より先に取り出されるべきなら入れ替えて、根の方向に繰り返す
+    fn sift_up(&mut self, mut i: usize) {
+        while let Some(pi) = d_ary::parent::<D>(i) {
+            if !self.less(i, pi) {
+                break;
+            }
+            self.array.swap(i, pi);
+            i = pi;
+        }
+    }
+}
+
+/// `FixedSizePriorityQueue::drain` で返される、要素をヒープ内の並び順で取り出すイテレータ
+pub struct Drain<'a, T> {
+    iter: core::slice::IterMut<'a, Option<T>>,
+}
+
+impl<T> Iterator for Drain<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.iter.find_map(Option::take)
+    }
+}
+
+impl<T> Drop for Drain<'_, T> {
+    fn drop(&mut self) {
+        self.iter.by_ref().for_each(|slot| *slot = None);
+    }
+}
+
+/// `FixedSizePriorityQueue::drain_sorted` で返される、要素を pop の順で取り出すイテレータ
+pub struct DrainSorted<'a, T, const N: usize, C, const D: usize>
+where
+    C: Compare<T>,
+{
+    queue: &'a mut FixedSizePriorityQueue<T, N, C, D>,
+}
+
+impl<T, const N: usize, C, const D: usize> Iterator for DrainSorted<'_, T, N, C, D>
+where
+    C: Compare<T>,
+{
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.queue.pop()
+    }
+}
+
+impl<T, const N: usize, C, const D: usize> Drop for DrainSorted<'_, T, N, C, D>
+where
+    C: Compare<T>,
+{
+    fn drop(&mut self) {
+        self.queue.clear();
+    }
+}
+
+/// `FixedSizePriorityQueue::peek_mut` で返される、先頭の要素を書き換えるためのガード
+///
+/// 書き換えた後にガードを drop すると、先頭から一度だけ並べ直す。pop してから push するより並べ替えが少ない。
+pub struct PeekMut<'a, T, const N: usize, C, const D: usize>
+where
+    C: Compare<T>,
+{
+    queue: &'a mut FixedSizePriorityQueue<T, N, C, D>,
+    modified: bool,
+}
+
+impl<T, const N: usize, C, const D: usize> PeekMut<'_, T, N, C, D>
+where
+    C: Compare<T>,
+{
+    // 書き換えずに先頭の要素を取り出す
+    pub fn pop(mut this: Self) -> T {
+        this.modified = false;
+        this.queue.pop().unwrap()
+    }
+}
+
+impl<T, const N: usize, C, const D: usize> core::ops::Deref for PeekMut<'_, T, N, C, D>
+where
+    C: Compare<T>,
+{
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.queue.item(0)
+    }
+}
+
+impl<T, const N: usize, C, const D: usize> core::ops::DerefMut for PeekMut<'_, T, N, C, D>
+where
+    C: Compare<T>,
+{
+    fn deref_mut(&mut self) -> &mut T {
+        self.modified = true;
+        self.queue.array[0].as_mut().unwrap()
+    }
+}
+
+impl<T, const N: usize, C, const D: usize> Drop for PeekMut<'_, T, N, C, D>
+where
+    C: Compare<T>,
+{
+    fn drop(&mut self) {
+        if self.modified {
+            self.queue.sift_down(0);
         }
     }
+}
 
-    // indexで指定した子の値が親の値よりが小さければ入れ替えて、根の方向に繰り返す
-    fn heapify_up(&mut self, ci: usize) {
-        let cv = self.array[ci].unwrap();
-        if let Some(pi) = Self::parent(ci) {
-            let pv = self.array[pi].unwrap();
-            if cv < pv {
-                self.array.swap(ci, pi);
-                self.heapify_up(pi)
+// 要素はヒープ内の並び順で出力する
+#[cfg(feature = "defmt")]
+impl<T, const N: usize, C, const D: usize> defmt::Format for FixedSizePriorityQueue<T, N, C, D>
+where
+    T: defmt::Format,
+    C: Compare<T>,
+{
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "FixedSizePriorityQueue {{ len: {=usize}, capacity: {=usize}, items: [",
+            self.size,
+            N
+        );
+        for (i, item) in self.iter().enumerate() {
+            if i > 0 {
+                defmt::write!(f, ", ");
             }
+            defmt::write!(f, "{}", item);
         }
+        defmt::write!(f, "], stats: {} }}", self.stats);
+    }
+}
+
+// 空いているところに詰めてからまとめてヒープを作り直す。入りきらない分は push と同じく overflow_policy に従う
+impl<T, const N: usize, C, const D: usize> Extend<T> for FixedSizePriorityQueue<T, N, C, D>
+where
+    C: Compare<T>,
+{
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        let mut iter = iter.into_iter();
+        let before = self.size;
+        while self.size < N {
+            match iter.next() {
+                Some(item) => {
+                    self.array[self.size] = Some(item);
+                    self.size += 1;
+                }
+                None => break,
+            }
+        }
+        if self.size > before {
+            self.stats.pushes = self
+                .stats
+                .pushes
+                .saturating_add((self.size - before) as u32);
+            self.stats.record_len(self.size);
+            self.rebuild();
+        }
+        for item in iter {
+            let _ = self.push(item);
+        }
+    }
+}
+
+impl<T, const N: usize, C, const D: usize> FromIterator<T> for FixedSizePriorityQueue<T, N, C, D>
+where
+    C: Compare<T> + Default,
+{
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut queue = Self::new();
+        queue.extend(iter);
+        queue
+    }
+}
+
+impl<T, const N: usize, C, const D: usize> Default for FixedSizePriorityQueue<T, N, C, D>
+where
+    C: Compare<T> + Default,
+{
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 #[cfg(test)]
 #[test]
 fn test1() {
+    // 配列の中身と要素数だけを比べる
+    fn contents<const N: usize>(
+        queue: &FixedSizePriorityQueue<u32, N>,
+    ) -> ([Option<u32>; N], usize) {
+        (queue.array, queue.size)
+    }
+
     const N: usize = 3;
     let mut queue = FixedSizePriorityQueue::<u32, N>::new();
+    assert_eq!(contents(&queue), ([None, None, None], 0));
+
+    assert_eq!(queue.pop(), None);
+    assert_eq!(contents(&queue), ([None, None, None], 0));
+
+    assert_eq!(queue.pop(), None);
+    assert_eq!(contents(&queue), ([None, None, None], 0));
+
+    assert!(queue.push(1).is_ok());
+    assert_eq!(contents(&queue), ([Some(1), None, None], 1));
+
+    assert_eq!(queue.pop(), Some(1));
+    assert_eq!(contents(&queue), ([None, None, None], 0));
+
+    assert!(queue.push(1).is_ok());
+    assert!(queue.push(3).is_ok());
+    assert_eq!(contents(&queue), ([Some(1), Some(3), None], 2));
+    assert_eq!(queue.pop(), Some(1));
+    assert_eq!(contents(&queue), ([Some(3), None, None], 1));
+
+    assert_eq!(queue.pop(), Some(3));
+    assert_eq!(contents(&queue), ([None, None, None], 0));
+
+    assert!(queue.push(1).is_ok());
+    assert!(queue.push(3).is_ok());
+    assert!(queue.push(2).is_ok());
+    assert_eq!(queue.push(4), Err(4));
+    assert_eq!(contents(&queue), ([Some(1), Some(3), Some(2)], 3)); // 子の左右が左 <= 右とは限らない
+    assert_eq!(queue.pop(), Some(1));
+    assert_eq!(queue.pop(), Some(2));
+    assert_eq!(queue.pop(), Some(3));
+}
+
+#[cfg(test)]
+#[test]
+fn test_non_copy() {
+    // 所有権を持つデータ(String)を含む要素もムーブで出し入れできる
+    #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+    struct Job {
+        priority: u32,
+        name: String,
+    }
+    let job = |priority: u32, name: &str| Job {
+        priority,
+        name: name.to_string(),
+    };
+
+    let mut queue = FixedSizePriorityQueue::<Job, 4>::new();
+    assert!(queue.push(job(3, "c")).is_ok());
+    assert!(queue.push(job(1, "a")).is_ok());
+    assert!(queue.push(job(4, "d")).is_ok());
+    assert!(queue.push(job(2, "b")).is_ok());
+    assert_eq!(queue.push(job(0, "overflow")), Err(job(0, "overflow")));
+
+    assert_eq!(queue.peek(), Some(&job(1, "a")));
+    assert_eq!(queue.pop(), Some(job(1, "a")));
+    assert_eq!(queue.pop(), Some(job(2, "b")));
+    assert!(queue.push(job(0, "z")).is_ok());
+    assert_eq!(queue.pop(), Some(job(0, "z")));
+    assert_eq!(queue.pop(), Some(job(3, "c")));
+    assert_eq!(queue.pop(), Some(job(4, "d")));
+    assert_eq!(queue.pop(), None);
+}
+
+#[cfg(test)]
+#[test]
+fn test_retain_remove_clear() {
+    // (時刻, LED番号) のような組を想定
+    let mut queue = FixedSizePriorityQueue::<(u32, usize), 8>::new();
+    for item in [(5, 0), (1, 1), (4, 0), (2, 2), (3, 0), (6, 1)] {
+        assert!(queue.push(item).is_ok());
+    }
+
+    // LED0 のものを全て消す
+    queue.retain(|&(_, led)| led != 0);
+    assert_eq!(queue.size, 3);
+    assert_eq!(queue.pop(), Some((1, 1)));
+    assert!(queue.push((0, 0)).is_ok());
+    assert!(queue.push((7, 2)).is_ok());
+
+    // LED2 のもののうち一番早いものだけ消す
+    assert_eq!(queue.remove_first(|&(_, led)| led == 2), Some((2, 2)));
+    assert_eq!(queue.remove_first(|&(_, led)| led == 3), None);
+    assert_eq!(queue.pop(), Some((0, 0)));
+    assert_eq!(queue.pop(), Some((6, 1)));
+    assert_eq!(queue.pop(), Some((7, 2)));
+    assert_eq!(queue.pop(), None);
+
+    for item in [(3, 0), (1, 1), (2, 2)] {
+        assert!(queue.push(item).is_ok());
+    }
+    queue.clear();
+    assert_eq!(queue.size, 0);
+    assert_eq!(queue.peek(), None);
+    assert!(queue.array.iter().all(Option::is_none));
+    assert!(queue.push((9, 0)).is_ok());
+    assert_eq!(queue.pop(), Some((9, 0)));
+}
+
+#[cfg(test)]
+#[test]
+fn test_comparators() {
+    let mut max_queue = FixedSizePriorityQueue::<u32, 4, MaxOrder>::new();
+    for item in [2, 4, 1, 3] {
+        assert!(max_queue.push(item).is_ok());
+    }
+    assert_eq!(max_queue.pop(), Some(4));
+    assert_eq!(max_queue.pop(), Some(3));
+    assert_eq!(max_queue.pop(), Some(2));
+    assert_eq!(max_queue.pop(), Some(1));
+
+    // 時刻だけで並べ、他のフィールドの順序は気にしない
+    #[derive(Debug, PartialEq)]
+    struct Scheduled {
+        schedule: u64,
+        led_num: usize,
+    }
+    let mut by_schedule =
+        FixedSizePriorityQueue::<Scheduled, 3, _>::with_comparator(ByKey(|s: &Scheduled| {
+            s.schedule
+        }));
+    assert!(by_schedule
+        .push(Scheduled {
+            schedule: 30,
+            led_num: 0
+        })
+        .is_ok());
+    assert!(by_schedule
+        .push(Scheduled {
+            schedule: 10,
+            led_num: 3
+        })
+        .is_ok());
+    assert!(by_schedule
+        .push(Scheduled {
+            schedule: 20,
+            led_num: 1
+        })
+        .is_ok());
+    assert_eq!(by_schedule.pop().map(|s| s.led_num), Some(3));
+    assert_eq!(by_schedule.pop().map(|s| s.led_num), Some(1));
+    assert_eq!(by_schedule.pop().map(|s| s.led_num), Some(0));
+
+    // 比較関数をそのまま渡す(長い文字列から)
+    let mut by_len = FixedSizePriorityQueue::<&str, 3, _>::with_comparator(|a: &&str, b: &&str| {
+        b.len().cmp(&a.len())
+    });
+    for item in ["ab", "abcd", "a"] {
+        assert!(by_len.push(item).is_ok());
+    }
+    assert_eq!(by_len.pop(), Some("abcd"));
+    assert_eq!(by_len.pop(), Some("ab"));
+    assert_eq!(by_len.pop(), Some("a"));
+}
+
+#[cfg(test)]
+#[test]
+fn test_inspect_and_drain() {
+    let mut queue = FixedSizePriorityQueue::<u32, 5>::new();
+    assert_eq!(queue.capacity(), 5);
+    assert!(queue.is_empty());
+    for item in [4, 1, 3, 5] {
+        assert!(queue.push(item).is_ok());
+    }
+    assert_eq!(queue.len(), 4);
+    assert!(!queue.is_empty());
+
+    // ヒープ内の並び順なので、中身だけを確認する
+    let mut items: Vec<u32> = queue.iter().copied().collect();
+    items.sort();
+    assert_eq!(items, vec![1, 3, 4, 5]);
+
+    let mut drained: Vec<u32> = queue.drain().collect();
+    drained.sort();
+    assert_eq!(drained, vec![1, 3, 4, 5]);
+    assert!(queue.is_empty());
+    assert_eq!(queue.iter().count(), 0);
+
+    // 途中で捨てても残りは消える
+    for item in [2, 9, 7] {
+        assert!(queue.push(item).is_ok());
+    }
+    assert!(queue.drain().next().is_some());
+    assert!(queue.is_empty());
+    assert!(queue.array.iter().all(Option::is_none));
+
+    for item in [2, 9, 7, 8] {
+        assert!(queue.push(item).is_ok());
+    }
+    let sorted: Vec<u32> = queue.drain_sorted().take(2).collect();
+    assert_eq!(sorted, vec![2, 7]);
+    assert!(queue.is_empty());
+
+    for item in [6, 2, 4] {
+        assert!(queue.push(item).is_ok());
+    }
+    assert_eq!(queue.drain_sorted().collect::<Vec<_>>(), vec![2, 4, 6]);
+
+    // Drain を捨てずに忘れても、空のキューとして扱う
+    for item in [3, 1] {
+        assert!(queue.push(item).is_ok());
+    }
+    core::mem::forget(queue.drain());
+    assert_eq!(queue.len(), 0);
+    assert_eq!(queue.peek(), None);
+    assert!(queue.peek_mut().is_none());
+    assert_eq!(queue.pop(), None);
+    assert!(queue.push(5).is_ok());
+    assert_eq!(queue.peek(), Some(&5));
+    queue.clear();
+
+    for item in [6, 2, 4] {
+        assert!(queue.push(item).is_ok());
+    }
     assert_eq!(
-        queue,
-        FixedSizePriorityQueue {
-            array: [None, None, None],
-            size: 0
-        }
+        queue.into_sorted_array(),
+        [Some(2), Some(4), Some(6), None, None]
     );
+}
 
-    assert_eq!(queue.pop(), None);
+#[cfg(test)]
+#[test]
+fn test_overflow_policy() {
+    let mut queue = FixedSizePriorityQueue::<u32, 4>::new();
+    assert_eq!(queue.overflow_policy(), OverflowPolicy::RejectNewest);
+    for item in [3, 1, 4, 2] {
+        assert!(queue.push(item).is_ok());
+    }
+    assert_eq!(queue.push(0), Err(0));
+    assert_eq!(queue.dropped(), 1);
+
+    // 優先度の低いもの(大きいもの)から捨てる
+    queue.set_overflow_policy(OverflowPolicy::EvictLowest);
+    assert_eq!(queue.push(0), Err(4));
+    assert_eq!(queue.push(5), Err(5));
+    assert_eq!(queue.push(3), Err(3)); // 同じなら先にいた方を残す
+    assert_eq!(queue.push(1), Err(3));
+    assert_eq!(queue.dropped(), 5);
+    assert_eq!(queue.len(), 4);
     assert_eq!(
-        queue,
-        FixedSizePriorityQueue {
-            array: [None, None, None],
-            size: 0
-        }
+        queue.into_sorted_array(),
+        [Some(0), Some(1), Some(1), Some(2)]
     );
+}
 
-    assert_eq!(queue.pop(), None);
+#[cfg(test)]
+#[test]
+fn test_bulk_construction() {
+    let queue = FixedSizePriorityQueue::<u32, 8>::from_array([5, 3, 8, 1, 9, 2]);
+    assert_eq!(queue.len(), 6);
+    assert_eq!(queue.peek(), Some(&1));
     assert_eq!(
-        queue,
-        FixedSizePriorityQueue {
-            array: [None, None, None],
-            size: 0
-        }
+        queue.into_sorted_array(),
+        [
+            Some(1),
+            Some(2),
+            Some(3),
+            Some(5),
+            Some(8),
+            Some(9),
+            None,
+            None
+        ]
     );
 
-    assert_eq!(queue.push(1), true);
+    // 入りきらない分は捨てられる
+    let mut queue: FixedSizePriorityQueue<u32, 4> = [7, 4, 6, 5, 1].into_iter().collect();
+    assert_eq!(queue.len(), 4);
+    assert_eq!(queue.dropped(), 1);
+    queue.clear();
+
+    queue.extend([3, 2]);
+    queue.extend([4]);
+    assert_eq!(queue.pop(), Some(2));
+
+    let other = FixedSizePriorityQueue::<u32, 2>::from_array([1, 9]);
+    assert!(queue.merge(other).is_ok());
+    assert_eq!(queue.len(), 4);
+    let other = FixedSizePriorityQueue::<u32, 2>::from_array([0]);
+    let other = queue.merge(other).unwrap_err();
+    assert_eq!(other.peek(), Some(&0));
     assert_eq!(
-        queue,
-        FixedSizePriorityQueue {
-            array: [Some(1), None, None],
-            size: 1
-        }
+        queue.into_sorted_array(),
+        [Some(1), Some(3), Some(4), Some(9)]
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn test_bulk_construction_with_comparator() {
+    // LED のキューと同じく、関数ポインタの ByKey で並べるキューもまとめて作れる
+    type ByFirst = ByKey<fn(&(u64, usize)) -> u64>;
+    let by_first: ByFirst = ByKey(|c| c.0);
+    let mut queue = FixedSizePriorityQueue::<(u64, usize), 8, ByFirst>::from_array_with_comparator(
+        [(300, 0), (100, 1), (250, 2), (70, 3)],
+        by_first,
     );
+    assert_eq!(queue.len(), 4);
+    assert_eq!(queue.stats().high_water_mark, 4);
+    queue.extend([(90, 0), (400, 1)]);
+    let order: Vec<u64> = queue.drain_sorted().map(|c| c.0).collect();
+    assert_eq!(order, [70, 90, 100, 250, 300, 400]);
+}
+
+#[cfg(test)]
+#[test]
+fn test_bulk_matches_push() {
+    // まとめて作っても、一つずつ push しても同じ順で取り出せる
+    let mut rand = xorshift(7);
+    let items: [u32; 32] = core::array::from_fn(|_| rand() % 100);
+
+    let mut pushed = FixedSizePriorityQueue::<u32, 32>::new();
+    for item in items {
+        assert!(pushed.push(item).is_ok());
+    }
+    let from_array = FixedSizePriorityQueue::<u32, 32>::from_array(items);
+    let mut extended = FixedSizePriorityQueue::<u32, 32>::from_iter(items[..10].iter().copied());
+    extended.extend(items[10..].iter().copied());
 
+    let expected = pushed.into_sorted_array();
+    assert_eq!(from_array.into_sorted_array(), expected);
+    assert_eq!(extended.into_sorted_array(), expected);
+}
+
+#[cfg(test)]
+#[test]
+fn test_peek_mut() {
+    let mut queue = FixedSizePriorityQueue::<u32, 8>::from_array([3, 1, 4, 5, 2]);
+    // 書き換えなければ並べ直さない
+    assert_eq!(queue.peek_mut().map(|head| *head), Some(1));
+    assert_eq!(queue.peek(), Some(&1));
+
+    // 先頭を後ろに回す
+    *queue.peek_mut().unwrap() = 10;
+    assert_eq!(queue.peek(), Some(&2));
+    // 先頭のままでよい場合
+    *queue.peek_mut().unwrap() = 0;
+    assert_eq!(queue.peek(), Some(&0));
+    assert_eq!(PeekMut::pop(queue.peek_mut().unwrap()), 0);
+
+    assert_eq!(queue.len(), 4);
+    let sorted: Vec<u32> = queue.drain_sorted().collect();
+    assert_eq!(sorted, [3, 4, 5, 10]);
+    assert!(queue.peek_mut().is_none());
+}
+
+#[cfg(test)]
+#[test]
+fn test_peek_mut_blink_schedule() {
+    // LedPins::_handle_command のように、BLINK のピン切り替えを先頭から取り出しては一周期後の切り替えを入れる。
+    // pop して push する場合と peek_mut で先頭を書き換える場合とで、同じ順に処理されることを確認する
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Scheduled {
+        schedule: u64,
+        led_num: usize,
+        high: bool,
+    }
+    type BySchedule = ByKey<fn(&Scheduled) -> u64>;
+    let by_schedule: BySchedule = ByKey(|c| c.schedule);
+    let periods = [100_000, 250_000, 70_000, 100_000];
+
+    let mut popped = FixedSizePriorityQueue::<Scheduled, 20, _>::with_comparator(by_schedule);
+    let mut peeked = FixedSizePriorityQueue::<Scheduled, 20, _>::with_comparator(by_schedule);
+    for (led_num, period) in periods.iter().enumerate() {
+        let command = Scheduled {
+            schedule: *period,
+            led_num,
+            high: false,
+        };
+        assert!(popped.push(command).is_ok());
+        assert!(peeked.push(command).is_ok());
+    }
+
+    let toggle = |c: &Scheduled| Scheduled {
+        schedule: c.schedule + periods[c.led_num],
+        led_num: c.led_num,
+        high: !c.high,
+    };
+    let mut now = 0;
+    while now < 2_000_000 {
+        now += 10_000;
+        // handle_schedule と同じく、今より前のものは全て処理する
+        let mut handled = Vec::new();
+        while let Some(&next) = popped.peek() {
+            if next.schedule > now {
+                break;
+            }
+            let _ = popped.pop();
+            handled.push(next);
+            assert!(popped.push(toggle(&next)).is_ok());
+        }
+        let mut handled_in_place = Vec::new();
+        while let Some(mut head) = peeked.peek_mut() {
+            if head.schedule > now {
+                break;
+            }
+            handled_in_place.push(*head);
+            *head = toggle(&head);
+        }
+        assert_eq!(handled_in_place.len(), handled.len());
+        // 同じ時刻の切り替えは順不同なので、時刻ごとの LED の状態で比べる
+        for (a, b) in handled.iter().zip(&handled_in_place) {
+            assert_eq!(a.schedule, b.schedule);
+        }
+        let mut a: Vec<_> = handled
+            .iter()
+            .map(|c| (c.schedule, c.led_num, c.high))
+            .collect();
+        let mut b: Vec<_> = handled_in_place
+            .iter()
+            .map(|c| (c.schedule, c.led_num, c.high))
+            .collect();
+        a.sort();
+        b.sort();
+        assert_eq!(a, b);
+    }
+    assert_eq!(peeked.len(), 4);
+}
+
+#[cfg(test)]
+#[test]
+fn test_const_construction() {
+    // static に直接置ける
+    static QUEUE: std::sync::Mutex<FixedSizePriorityQueue<u32, 4>> =
+        std::sync::Mutex::new(FixedSizePriorityQueue::with_comparator(MinOrder));
+    type ByFirst = ByKey<fn(&(u32, char)) -> u32>;
+    const BY_KEY: FixedSizePriorityQueue<(u32, char), 4, ByFirst> =
+        FixedSizePriorityQueue::with_comparator(ByKey(|c| c.0));
+
+    let mut queue = QUEUE.lock().unwrap();
+    for item in [3, 1, 2] {
+        assert!(queue.push(item).is_ok());
+    }
     assert_eq!(queue.pop(), Some(1));
+
+    let mut by_key = BY_KEY;
+    assert!(by_key.push((2, 'a')).is_ok());
+    assert!(by_key.push((1, 'b')).is_ok());
+    assert_eq!(by_key.pop(), Some((1, 'b')));
     assert_eq!(
-        queue,
-        FixedSizePriorityQueue {
-            array: [None, None, None],
-            size: 0
-        }
+        FixedSizePriorityQueue::<u32, 4, MaxOrder>::default(),
+        FixedSizePriorityQueue::with_comparator(MaxOrder)
     );
+}
 
-    assert_eq!(queue.push(1), true);
-    assert_eq!(queue.push(3), true);
+#[cfg(test)]
+#[test]
+fn test_stats() {
+    let mut queue = FixedSizePriorityQueue::<u32, 4>::from_array([5, 6]);
     assert_eq!(
-        queue,
-        FixedSizePriorityQueue {
-            array: [Some(1), Some(3), None],
-            size: 2
+        queue.stats(),
+        QueueStats {
+            high_water_mark: 2,
+            pushes: 0,
+            dropped: 0
         }
     );
+
+    for item in [3, 1, 4] {
+        let _ = queue.push(item);
+    }
     assert_eq!(queue.pop(), Some(1));
+    assert_eq!(queue.pop(), Some(3));
+    queue.extend([2, 7, 8]);
     assert_eq!(
-        queue,
-        FixedSizePriorityQueue {
-            array: [Some(3), None, None],
-            size: 1
+        queue.stats(),
+        QueueStats {
+            high_water_mark: 4,
+            pushes: 6,
+            dropped: 2
         }
     );
+    assert_eq!(queue.dropped(), queue.stats().dropped);
 
-    assert_eq!(queue.pop(), Some(3));
+    queue.pop();
+    queue.reset_stats();
     assert_eq!(
-        queue,
-        FixedSizePriorityQueue {
-            array: [None, None, None],
-            size: 0
+        queue.stats(),
+        QueueStats {
+            high_water_mark: 3,
+            pushes: 0,
+            dropped: 0
         }
     );
+}
 
-    assert_eq!(queue.push(1), true);
-    assert_eq!(queue.push(3), true);
-    assert_eq!(queue.push(2), true);
-    assert_eq!(queue.push(4), false);
-    assert_eq!(
-        queue,
-        FixedSizePriorityQueue {
-            array: [Some(1), Some(3), Some(2)], // 子の左右が左 <= 右とは限らない
-            size: 3
+#[cfg(test)]
+fn xorshift(mut seed: u32) -> impl FnMut() -> u32 {
+    move || {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        seed
+    }
+}
+
+#[cfg(test)]
+fn check_against_std<const D: usize>() {
+    use std::cmp::Reverse;
+    use std::collections::BinaryHeap;
+
+    // push と pop を乱数で混ぜ、std の BinaryHeap と同じ順で取り出せることを確かめる
+    let mut rand = xorshift(11);
+    let mut queue = FixedSizePriorityQueue::<u32, 64, MinOrder, D>::new();
+    let mut std_heap = BinaryHeap::new();
+    for _ in 0..100_000 {
+        if rand().is_multiple_of(2) && std_heap.len() < 64 {
+            let item = rand() % 1000;
+            assert!(queue.push(item).is_ok());
+            std_heap.push(Reverse(item));
+        } else {
+            assert_eq!(queue.pop(), std_heap.pop().map(|Reverse(item)| item));
         }
+        assert_eq!(queue.peek(), std_heap.peek().map(|Reverse(item)| item));
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_arity_matches_std() {
+    check_against_std::<2>();
+    check_against_std::<3>();
+    check_against_std::<4>();
+    check_against_std::<8>();
+
+    // 葉の位置の計算が D に合っていることを、まとめて作る場合と溢れたときの入れ替えで確かめる
+    let mut queue =
+        FixedSizePriorityQueue::<u32, 10, MinOrder, 4>::from_array([9, 3, 7, 1, 8, 2, 6, 0]);
+    queue.set_overflow_policy(OverflowPolicy::EvictLowest);
+    assert!(queue.push(5).is_ok());
+    assert!(queue.push(4).is_ok());
+    assert_eq!(queue.push(1), Err(9));
+    assert_eq!(
+        queue.into_sorted_array(),
+        [0, 1, 1, 2, 3, 4, 5, 6, 7, 8].map(Some)
     );
-    assert_eq!(queue.pop(), Some(1));
-    assert_eq!(queue.pop(), Some(2));
-    assert_eq!(queue.pop(), Some(3));
+}
+
+#[cfg(test)]
+#[test]
+fn test_arity_in_other_queues() {
+    use core::mem::MaybeUninit;
+    use std::cmp::Reverse;
+    use std::collections::BinaryHeap;
+
+    // 子の数を変えたキューも、std の BinaryHeap と同じ順で取り出せる
+    let mut rand = xorshift(23);
+    let mut indexed = IndexedPriorityQueue::<u32, 32, MinOrder, 4>::new();
+    let mut keyed = KeyedPriorityQueue::<u32, (), 32, MinOrder, 3>::new();
+    let mut buffer = [const { MaybeUninit::uninit() }; 32];
+    let mut queue_ref = PriorityQueueRef::<u32, MinOrder, 8>::new(&mut buffer);
+    let mut std_heap = BinaryHeap::new();
+    let mut next_key = 0;
+    for _ in 0..20_000 {
+        if rand().is_multiple_of(2) && std_heap.len() < 32 {
+            let item = rand() % 1000;
+            assert!(indexed.push(item).is_ok());
+            // 空いているキーを順に使う
+            while keyed.contains_key(next_key) {
+                next_key = (next_key + 1) % 32;
+            }
+            assert_eq!(keyed.insert_or_update(next_key, item, ()), None);
+            assert!(queue_ref.push(item).is_ok());
+            std_heap.push(Reverse(item));
+        } else {
+            let expected = std_heap.pop().map(|Reverse(item)| item);
+            assert_eq!(indexed.pop(), expected);
+            assert_eq!(keyed.pop().map(|(_, p, _)| p), expected);
+            assert_eq!(queue_ref.pop(), expected);
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn bench_arity_vs_std_binary_heap() {
+    // LED のスケジュールのように、取り出しては少し先の時刻を入れ直す使い方での比較
+    // (cargo test --release -- --nocapture bench で確認する)
+    use std::cmp::Reverse;
+    use std::collections::BinaryHeap;
+    use std::time::Instant;
+
+    const ROUNDS: u64 = 200_000;
+    fn run<const D: usize>() -> (u64, std::time::Duration) {
+        let start = Instant::now();
+        let mut queue = FixedSizePriorityQueue::<u64, 32, MinOrder, D>::new();
+        for i in 0..32 {
+            queue.push(i * 37 % 100).unwrap();
+        }
+        let mut sum = 0;
+        for i in 0..ROUNDS {
+            let now = queue.pop().unwrap();
+            sum += now;
+            queue.push(now + 1 + i % 97).unwrap();
+        }
+        (sum, start.elapsed())
+    }
+
+    let start = Instant::now();
+    let mut std_heap = BinaryHeap::with_capacity(32);
+    for i in 0..32u64 {
+        std_heap.push(Reverse(i * 37 % 100));
+    }
+    let mut std_sum = 0;
+    for i in 0..ROUNDS {
+        let Reverse(now) = std_heap.pop().unwrap();
+        std_sum += now;
+        std_heap.push(Reverse(now + 1 + i % 97));
+    }
+    let std_elapsed = start.elapsed();
+
+    let results = [(2, run::<2>()), (4, run::<4>()), (8, run::<8>())];
+    println!("std BinaryHeap: {:?}", std_elapsed);
+    for (arity, (sum, elapsed)) in results {
+        assert_eq!(sum, std_sum);
+        println!("{}-ary FixedSizePriorityQueue: {:?}", arity, elapsed);
+    }
 }
@@ -0,0 +1,306 @@
+use crate::compare::{Compare, MinOrder};
+use crate::OverflowPolicy;
+use core::cmp::Ordering;
+
+/// 最も優先度の高い要素と低い要素の両方を O(log N) で取り出せる優先度付きキュー (min-max heap)
+///
+/// 深さが偶数の段では子孫の中で C で最も先に取り出されるもの(min)、奇数の段では最も後になるもの(max)を持つ。
+/// いっぱいのときに `OverflowPolicy::EvictLowest` で最も優先度の低い要素を捨てるのも O(log N) になる。
+#[derive(Debug, PartialEq)]
+pub struct MinMaxPriorityQueue<T, const N: usize, C = MinOrder> {
+    array: [Option<T>; N],
+    size: usize,
+    cmp: C,
+    overflow_policy: OverflowPolicy,
+    dropped: u32,
+}
+
+impl<T, const N: usize, C> MinMaxPriorityQueue<T, N, C>
+where
+    C: Compare<T>,
+{
+    pub fn new() -> Self
+    where
+        C: Default,
+    {
+        Self::with_comparator(C::default())
+    }
+
+    pub fn with_comparator(cmp: C) -> Self {
+        MinMaxPriorityQueue {
+            array: core::array::from_fn(|_| None),
+            size: 0,
+            cmp,
+            overflow_policy: OverflowPolicy::RejectNewest,
+            dropped: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        N
+    }
+
+    // ヒープ内の並び順(配列の順)で要素を見る。取り出される順とは限らない
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.array[..self.size].iter().flatten()
+    }
+
+    // 最も先に取り出される要素
+    pub fn peek_min(&self) -> Option<&T> {
+        self.array.first().and_then(|opt| opt.as_ref())
+    }
+
+    // 最も後に取り出される要素
+    pub fn peek_max(&self) -> Option<&T> {
+        self.max_index().map(|i| self.item(i))
+    }
+
+    pub fn pop_min(&mut self) -> Option<T> {
+        if self.size > 0 {
+            Some(self.remove_at(0))
+        } else {
+            None
+        }
+    }
+
+    pub fn pop_max(&mut self) -> Option<T> {
+        self.max_index().map(|i| self.remove_at(i))
+    }
+
+    // いっぱいで追加できなかった場合は、overflow_policy に従って捨てた要素を Err で返す
+    pub fn push(&mut self, item: T) -> Result<(), T> {
+        if self.size < N {
+            self.array[self.size] = Some(item);
+            self.size += 1;
+            self.bubble_up(self.size - 1);
+            Ok(())
+        } else {
+            self.dropped = self.dropped.saturating_add(1);
+            match self.overflow_policy {
+                OverflowPolicy::RejectNewest => Err(item),
+                OverflowPolicy::EvictLowest => match self.peek_max() {
+                    Some(max) if self.cmp.compare(&item, max) == Ordering::Less => {
+                        let evicted = self.pop_max().unwrap();
+                        if self.push(item).is_err() {
+                            unreachable!("queue has room");
+                        }
+                        Err(evicted)
+                    }
+                    _ => Err(item),
+                },
+            }
+        }
+    }
+
+    pub fn overflow_policy(&self) -> OverflowPolicy {
+        self.overflow_policy
+    }
+
+    pub fn set_overflow_policy(&mut self, overflow_policy: OverflowPolicy) {
+        self.overflow_policy = overflow_policy;
+    }
+
+    // いっぱいのときに push されて捨てた要素の数
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+
+    pub fn clear(&mut self) {
+        for slot in self.array.iter_mut() {
+            *slot = None;
+        }
+        self.size = 0;
+    }
+
+    fn item(&self, i: usize) -> &T {
+        self.array[i].as_ref().unwrap()
+    }
+
+    // i が j と比べて order の側にあるか
+    fn is(&self, i: usize, order: Ordering, j: usize) -> bool {
+        self.cmp.compare(self.item(i), self.item(j)) == order
+    }
+
+    // 深さが偶数の段は min、奇数の段は max を持つ。min の段では Less の側、max の段では Greater の側が上に来る
+    fn order_at(i: usize) -> Ordering {
+        if (i + 1).ilog2().is_multiple_of(2) {
+            Ordering::Less
+        } else {
+            Ordering::Greater
+        }
+    }
+
+    fn max_index(&self) -> Option<usize> {
+        match self.size {
+            0 => None,
+            1 => Some(0),
+            2 => Some(1),
+            _ if self.is(2, Ordering::Greater, 1) => Some(2),
+            _ => Some(1),
+        }
+    }
+
+    // 指定した位置の要素を末尾と入れ替えて取り出し、入れ替えた要素を下に動かす
+    // (取り出すのは根か根の子なので、上に動かす必要はない)
+    fn remove_at(&mut self, i: usize) -> T {
+        let li = self.size - 1;
+        self.array.swap(i, li);
+        let item = self.array[li].take().unwrap();
+        self.size -= 1;
+        if i < self.size {
+            self.trickle_down(i);
+        }
+        item
+    }
+
+    fn bubble_up(&mut self, i: usize) {
+        if i == 0 {
+            return;
+        }
+        let parent = (i - 1) / 2;
+        let order = Self::order_at(i);
+        if self.is(i, order.reverse(), parent) {
+            self.array.swap(i, parent);
+            self.bubble_up_grandparent(parent, order.reverse());
+        } else {
+            self.bubble_up_grandparent(i, order);
+        }
+    }
+
+    // 同じ種類の段(祖父母)をたどって上に動かす
+    fn bubble_up_grandparent(&mut self, mut i: usize, order: Ordering) {
+        while i >= 3 {
+            let grandparent = ((i - 1) / 2 - 1) / 2;
+            if !self.is(i, order, grandparent) {
+                break;
+            }
+            self.array.swap(i, grandparent);
+            i = grandparent;
+        }
+    }
+
+    fn trickle_down(&mut self, mut i: usize) {
+        let order = Self::order_at(i);
+        loop {
+            // 子と孫の中で最も order の側にあるもの。添字の計算があふれたら子はいない
+            let first_child = match i.checked_mul(2).and_then(|c| c.checked_add(1)) {
+                Some(c) if c < self.size => c,
+                _ => break,
+            };
+            let first_grandchild = first_child.checked_mul(2).and_then(|g| g.checked_add(1));
+            let children = first_child..(first_child + 2).min(self.size);
+            let grandchildren = first_grandchild
+                .map(|g| g..g.saturating_add(4).min(self.size))
+                .unwrap_or(0..0);
+            let mut m = first_child;
+            for j in children.chain(grandchildren.clone()) {
+                if self.is(j, order, m) {
+                    m = j;
+                }
+            }
+
+            if !self.is(m, order, i) {
+                break;
+            }
+            self.array.swap(i, m);
+            if !grandchildren.contains(&m) {
+                break;
+            }
+            // 孫と入れ替えた場合、間の段(逆の種類)との関係を直してから続ける
+            let parent = (m - 1) / 2;
+            if self.is(m, order.reverse(), parent) {
+                self.array.swap(m, parent);
+            }
+            i = m;
+        }
+    }
+}
+
+impl<T, const N: usize, C> Default for MinMaxPriorityQueue<T, N, C>
+where
+    C: Compare<T> + Default,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_min_max() {
+    let mut queue = MinMaxPriorityQueue::<u32, 8>::new();
+    assert_eq!(queue.peek_min(), None);
+    assert_eq!(queue.peek_max(), None);
+    assert_eq!(queue.pop_max(), None);
+
+    for item in [5, 3, 8, 1, 9, 2, 7] {
+        assert!(queue.push(item).is_ok());
+    }
+    assert_eq!(queue.peek_min(), Some(&1));
+    assert_eq!(queue.peek_max(), Some(&9));
+    assert_eq!(queue.pop_max(), Some(9));
+    assert_eq!(queue.pop_min(), Some(1));
+    assert_eq!(queue.pop_max(), Some(8));
+    assert_eq!(queue.pop_max(), Some(7));
+    assert_eq!(queue.pop_min(), Some(2));
+    assert_eq!(queue.pop_min(), Some(3));
+    assert_eq!(queue.peek_min(), queue.peek_max());
+    assert_eq!(queue.pop_max(), Some(5));
+    assert!(queue.is_empty());
+
+    // 乱数で push と両端からの pop を混ぜ、整列した Vec と比べる
+    let mut rand = crate::xorshift(7);
+    let mut queue = MinMaxPriorityQueue::<u32, 64>::new();
+    let mut expected: Vec<u32> = Vec::new();
+    for _ in 0..20_000 {
+        match rand() % 3 {
+            0 => {
+                assert_eq!(
+                    queue.pop_min(),
+                    (!expected.is_empty()).then(|| expected.remove(0))
+                );
+            }
+            1 => assert_eq!(queue.pop_max(), expected.pop()),
+            _ => {
+                let item = rand() % 100;
+                if queue.push(item).is_ok() {
+                    let at = expected.partition_point(|&e| e < item);
+                    expected.insert(at, item);
+                }
+            }
+        }
+        assert_eq!(queue.len(), expected.len());
+        assert_eq!(queue.peek_min(), expected.first());
+        assert_eq!(queue.peek_max(), expected.last());
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_min_max_evicts_furthest() {
+    use crate::ByKey;
+
+    // (時刻, LED番号) のスケジュールがいっぱいなら、一番先の時刻のものを捨てる
+    let mut queue =
+        MinMaxPriorityQueue::<(u32, usize), 4, _>::with_comparator(ByKey(|c: &(u32, usize)| c.0));
+    queue.set_overflow_policy(OverflowPolicy::EvictLowest);
+    for item in [(300, 0), (100, 1), (400, 2), (200, 3)] {
+        assert!(queue.push(item).is_ok());
+    }
+    assert_eq!(queue.push((150, 0)), Err((400, 2)));
+    assert_eq!(queue.push((500, 1)), Err((500, 1)));
+    assert_eq!(queue.dropped(), 2);
+    assert_eq!(queue.pop_min(), Some((100, 1)));
+    assert_eq!(queue.pop_min(), Some((150, 0)));
+    assert_eq!(queue.pop_min(), Some((200, 3)));
+    assert_eq!(queue.pop_min(), Some((300, 0)));
+    assert_eq!(queue.pop_min(), None);
+}
@@ -0,0 +1,234 @@
+use crate::compare::{Compare, MinOrder};
+use crate::d_ary;
+use crate::OverflowPolicy;
+use core::cmp::Ordering;
+use core::mem::MaybeUninit;
+
+/// 呼び出し側が用意した領域の上に作る優先度付きキュー
+///
+/// 容量は渡された `&mut [MaybeUninit<T>]` の長さで決まるので、起動時に決めることができる。
+/// 先頭から `len()` 個が初期化済みの要素で、ヒープの並びになっている。
+/// キューを drop すると残っている要素も drop するが、領域そのものは呼び出し側に返る。
+/// D はヒープの各ノードの子の数で、`FixedSizePriorityQueue` と同じく 2 以上を選べる。
+pub struct PriorityQueueRef<'a, T, C = MinOrder, const D: usize = 2> {
+    buffer: &'a mut [MaybeUninit<T>],
+    size: usize,
+    cmp: C,
+    overflow_policy: OverflowPolicy,
+    dropped: u32,
+}
+
+impl<'a, T, C, const D: usize> PriorityQueueRef<'a, T, C, D>
+where
+    C: Compare<T>,
+{
+    pub fn new(buffer: &'a mut [MaybeUninit<T>]) -> Self
+    where
+        C: Default,
+    {
+        Self::with_comparator(buffer, C::default())
+    }
+
+    pub fn with_comparator(buffer: &'a mut [MaybeUninit<T>], cmp: C) -> Self {
+        d_ary::assert_arity::<D>();
+        PriorityQueueRef {
+            buffer,
+            size: 0,
+            cmp,
+            overflow_policy: OverflowPolicy::RejectNewest,
+            dropped: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn peek(&self) -> Option<&T> {
+        if self.size > 0 {
+            Some(self.item(0))
+        } else {
+            None
+        }
+    }
+
+    // ヒープ内の並び順(配列の順)で要素を見る。取り出される順とは限らない
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        (0..self.size).map(|i| self.item(i))
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        if self.size == 0 {
+            return None;
+        }
+        self.size -= 1;
+        self.buffer.swap(0, self.size);
+        let root = unsafe { self.buffer[self.size].assume_init_read() };
+        self.sift_down(0);
+        Some(root)
+    }
+
+    // いっぱいで追加できなかった場合は、overflow_policy に従って捨てた要素を Err で返す
+    pub fn push(&mut self, item: T) -> Result<(), T> {
+        if self.size < self.buffer.len() {
+            self.buffer[self.size].write(item);
+            self.size += 1;
+            self.sift_up(self.size - 1);
+            Ok(())
+        } else {
+            self.dropped = self.dropped.saturating_add(1);
+            match self.overflow_policy {
+                OverflowPolicy::RejectNewest => Err(item),
+                OverflowPolicy::EvictLowest => match self.lowest() {
+                    Some(li) if self.cmp.compare(&item, self.item(li)) == Ordering::Less => {
+                        // 葉にあるので上に動かすだけでよい
+                        let evicted =
+                            core::mem::replace(unsafe { self.buffer[li].assume_init_mut() }, item);
+                        self.sift_up(li);
+                        Err(evicted)
+                    }
+                    _ => Err(item),
+                },
+            }
+        }
+    }
+
+    pub fn overflow_policy(&self) -> OverflowPolicy {
+        self.overflow_policy
+    }
+
+    pub fn set_overflow_policy(&mut self, overflow_policy: OverflowPolicy) {
+        self.overflow_policy = overflow_policy;
+    }
+
+    // いっぱいのときに push されて捨てた要素の数
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+
+    pub fn clear(&mut self) {
+        let size = core::mem::replace(&mut self.size, 0);
+        for slot in &mut self.buffer[..size] {
+            unsafe { slot.assume_init_drop() };
+        }
+    }
+
+    // size より前の要素は初期化済み
+    fn item(&self, i: usize) -> &T {
+        debug_assert!(i < self.size);
+        unsafe { self.buffer[i].assume_init_ref() }
+    }
+
+    // i の要素が j の要素より先に取り出されるべきか
+    fn less(&self, i: usize, j: usize) -> bool {
+        self.cmp.compare(self.item(i), self.item(j)) == Ordering::Less
+    }
+
+    // 最も優先度の低い要素(最後に取り出されるもの)の位置。葉のどれかにある
+    fn lowest(&self) -> Option<usize> {
+        (d_ary::first_leaf::<D>(self.size)..self.size).reduce(|lowest, i| {
+            if self.less(lowest, i) {
+                i
+            } else {
+                lowest
+            }
+        })
+    }
+
+    fn sift_up(&mut self, mut i: usize) {
+        while let Some(pi) = d_ary::parent::<D>(i) {
+            if !self.less(i, pi) {
+                break;
+            }
+            self.buffer.swap(i, pi);
+            i = pi;
+        }
+    }
+
+    fn sift_down(&mut self, mut i: usize) {
+        while let Some(children) = d_ary::children::<D>(i, self.size) {
+            let child = (children.start + 1..children.end).fold(children.start, |child, c| {
+                if self.less(c, child) {
+                    c
+                } else {
+                    child
+                }
+            });
+            if !self.less(child, i) {
+                break;
+            }
+            self.buffer.swap(i, child);
+            i = child;
+        }
+    }
+}
+
+impl<T, C, const D: usize> Drop for PriorityQueueRef<'_, T, C, D> {
+    fn drop(&mut self) {
+        for slot in &mut self.buffer[..self.size] {
+            unsafe { slot.assume_init_drop() };
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_queue_ref() {
+    // 容量は実行時に決める
+    let capacity = 5;
+    let mut buffer = Vec::new();
+    buffer.resize_with(capacity, MaybeUninit::<u32>::uninit);
+
+    let mut queue = PriorityQueueRef::<u32>::new(&mut buffer);
+    assert_eq!(queue.capacity(), 5);
+    assert_eq!(queue.pop(), None);
+    for item in [4, 2, 5, 1, 3] {
+        assert!(queue.push(item).is_ok());
+    }
+    assert_eq!(queue.push(0), Err(0));
+    assert_eq!(queue.dropped(), 1);
+    assert_eq!(queue.peek(), Some(&1));
+
+    queue.set_overflow_policy(OverflowPolicy::EvictLowest);
+    assert_eq!(queue.push(0), Err(5));
+    assert_eq!(queue.iter().count(), 5);
+    for expected in 0..5 {
+        assert_eq!(queue.pop(), Some(expected));
+    }
+    assert!(queue.is_empty());
+}
+
+#[cfg(test)]
+#[test]
+fn test_queue_ref_drops_items() {
+    use std::rc::Rc;
+
+    let counter = Rc::new(());
+    let mut buffer: [MaybeUninit<(u32, Rc<()>)>; 8] = [const { MaybeUninit::uninit() }; 8];
+    {
+        let mut queue = PriorityQueueRef::<_, _>::with_comparator(
+            &mut buffer,
+            |a: &(u32, Rc<()>), b: &(u32, Rc<()>)| a.0.cmp(&b.0),
+        );
+        for i in 0..6 {
+            assert!(queue.push((i, counter.clone())).is_ok());
+        }
+        assert_eq!(queue.pop().map(|(i, _)| i), Some(0));
+        assert_eq!(Rc::strong_count(&counter), 6);
+        queue.clear();
+        assert_eq!(Rc::strong_count(&counter), 1);
+        for i in 0..3 {
+            assert!(queue.push((i, counter.clone())).is_ok());
+        }
+    }
+    // キューを drop すると残っていた要素も drop される
+    assert_eq!(Rc::strong_count(&counter), 1);
+}
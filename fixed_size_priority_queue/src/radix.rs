@@ -0,0 +1,242 @@
+/// `RadixHeap` に入れる要素のキー
+///
+/// 取り出されるキーは単調に増えていく(最後に取り出したキーより小さいものは入れられない)ことを前提にする。
+pub trait RadixKey {
+    fn radix_key(&self) -> u64;
+}
+
+macro_rules! impl_radix_key {
+    ($($t:ty),*) => {
+        $(impl RadixKey for $t {
+            fn radix_key(&self) -> u64 {
+                *self as u64
+            }
+        })*
+    };
+}
+
+impl_radix_key!(u8, u16, u32, u64, usize);
+
+const BUCKETS: usize = 65;
+const NIL: usize = usize::MAX;
+
+/// 単調に増えていくキー(タイマーの時刻など)専用の固定長の radix heap
+///
+/// 最後に取り出したキー `last` との xor の最上位ビットの位置ごとにバケツに分けて持つ。
+/// push は O(1) で、pop はバケツ 0 が空のときに空でない最初のバケツの最小のキーを `last` にして分け直す
+/// (1 つの要素が分け直されるのは高々 64 回なので償却 O(log C))。
+#[derive(Debug)]
+pub struct RadixHeap<T, const N: usize> {
+    items: [Option<T>; N],
+    keys: [u64; N],
+    // バケツごとの単方向リスト。空いている要素も free からつなぐ
+    next: [usize; N],
+    buckets: [usize; BUCKETS],
+    free: usize,
+    last: u64,
+    size: usize,
+}
+
+impl<T, const N: usize> RadixHeap<T, N>
+where
+    T: RadixKey,
+{
+    pub fn new() -> Self {
+        RadixHeap {
+            items: core::array::from_fn(|_| None),
+            keys: [0; N],
+            next: core::array::from_fn(|i| if i + 1 < N { i + 1 } else { NIL }),
+            buckets: [NIL; BUCKETS],
+            free: if N > 0 { 0 } else { NIL },
+            last: 0,
+            size: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        N
+    }
+
+    // 最後に取り出したキー。これより小さいキーは push できない
+    pub fn last_key(&self) -> u64 {
+        self.last
+    }
+
+    // バケツ 0 が空のときは、空でない最初のバケツの中から最小のものを探す
+    pub fn peek(&self) -> Option<&T> {
+        let b = (0..BUCKETS).find(|&b| self.buckets[b] != NIL)?;
+        self.items[self.min_in(b)].as_ref()
+    }
+
+    // いっぱいのとき、またはキーが last_key より小さいときは item をそのまま返す
+    pub fn push(&mut self, item: T) -> Result<(), T> {
+        let key = item.radix_key();
+        if self.free == NIL || key < self.last {
+            return Err(item);
+        }
+        let i = self.free;
+        self.free = self.next[i];
+        self.items[i] = Some(item);
+        self.keys[i] = key;
+        self.link(i);
+        self.size += 1;
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        if self.buckets[0] == NIL {
+            self.redistribute()?;
+        }
+        let i = self.buckets[0];
+        self.buckets[0] = self.next[i];
+        let item = self.items[i].take();
+        self.next[i] = self.free;
+        self.free = i;
+        self.size -= 1;
+        item
+    }
+
+    fn bucket(&self, key: u64) -> usize {
+        (u64::BITS - (key ^ self.last).leading_zeros()) as usize
+    }
+
+    fn link(&mut self, i: usize) {
+        let b = self.bucket(self.keys[i]);
+        self.next[i] = self.buckets[b];
+        self.buckets[b] = i;
+    }
+
+    // バケツ b の中でキーが最小の要素の位置
+    fn min_in(&self, b: usize) -> usize {
+        let mut min = self.buckets[b];
+        let mut i = self.next[min];
+        while i != NIL {
+            if self.keys[i] < self.keys[min] {
+                min = i;
+            }
+            i = self.next[i];
+        }
+        min
+    }
+
+    // 空でない最初のバケツの最小のキーを last にして、そのバケツの要素を下のバケツに分け直す。
+    // 最小のキーの要素はバケツ 0 に入る。空なら None
+    fn redistribute(&mut self) -> Option<()> {
+        let b = (1..BUCKETS).find(|&b| self.buckets[b] != NIL)?;
+        self.last = self.keys[self.min_in(b)];
+        let mut i = core::mem::replace(&mut self.buckets[b], NIL);
+        while i != NIL {
+            let next = self.next[i];
+            self.link(i);
+            i = next;
+        }
+        Some(())
+    }
+}
+
+impl<T, const N: usize> Default for RadixHeap<T, N>
+where
+    T: RadixKey,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_radix_heap() {
+    let mut heap = RadixHeap::<u64, 8>::new();
+    assert_eq!(heap.pop(), None);
+    for key in [30, 10, 20, 10, 1000] {
+        assert!(heap.push(key).is_ok());
+    }
+    assert_eq!(heap.peek(), Some(&10));
+    assert_eq!(heap.pop(), Some(10));
+    assert_eq!(heap.pop(), Some(10));
+    assert_eq!(heap.last_key(), 10);
+
+    // last_key より前には入れられない
+    assert_eq!(heap.push(5), Err(5));
+    assert!(heap.push(10).is_ok());
+    assert!(heap.push(25).is_ok());
+    assert_eq!(heap.pop(), Some(10));
+    assert_eq!(heap.pop(), Some(20));
+    assert_eq!(heap.pop(), Some(25));
+    assert_eq!(heap.pop(), Some(30));
+    assert_eq!(heap.peek(), Some(&1000));
+    assert_eq!(heap.pop(), Some(1000));
+    assert_eq!(heap.pop(), None);
+    assert!(heap.is_empty());
+
+    // 空になっても last_key は戻らない
+    assert_eq!(heap.push(999), Err(999));
+    assert!(heap.push(u64::MAX).is_ok());
+    assert_eq!(heap.pop(), Some(u64::MAX));
+
+    let mut full = RadixHeap::<u32, 2>::new();
+    assert!(full.push(1).is_ok());
+    assert!(full.push(2).is_ok());
+    assert_eq!(full.push(3), Err(3));
+}
+
+#[cfg(test)]
+#[test]
+fn bench_radix_heap_vs_binary_heap() {
+    // LED のスケジュールのように「今」より後の時刻を入れては一番早いものを取り出す使い方で、
+    // FixedSizePriorityQueue と取り出される順が同じになることを確かめ、かかった時間を表示する
+    // (cargo test --release -- --nocapture bench で確認する)
+    use crate::FixedSizePriorityQueue;
+    use std::time::Instant;
+
+    const N: usize = 64;
+    const ROUNDS: usize = 200_000;
+    let mut seed = 3u64;
+    let mut rand = move || {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        seed
+    };
+    let delays: Vec<u64> = (0..ROUNDS + N).map(|_| rand() % 100_000).collect();
+
+    let start = Instant::now();
+    let mut radix = RadixHeap::<u64, N>::new();
+    let mut radix_out = Vec::with_capacity(ROUNDS);
+    for &delay in &delays[..N] {
+        radix.push(delay).unwrap();
+    }
+    for &delay in &delays[N..] {
+        let now = radix.pop().unwrap();
+        radix_out.push(now);
+        radix.push(now + delay).unwrap();
+    }
+    let radix_time = start.elapsed();
+
+    let start = Instant::now();
+    let mut binary = FixedSizePriorityQueue::<u64, N>::new();
+    let mut binary_out = Vec::with_capacity(ROUNDS);
+    for &delay in &delays[..N] {
+        binary.push(delay).unwrap();
+    }
+    for &delay in &delays[N..] {
+        let now = binary.pop().unwrap();
+        binary_out.push(now);
+        binary.push(now + delay).unwrap();
+    }
+    let binary_time = start.elapsed();
+
+    assert_eq!(radix_out, binary_out);
+    println!(
+        "{} rounds with {} items: RadixHeap {:?}, FixedSizePriorityQueue {:?}",
+        ROUNDS, N, radix_time, binary_time
+    );
+}
@@ -0,0 +1,188 @@
+// キューの中身をヒープ内の並び順のまま要素の列として保存する。比較関数や overflow_policy、
+// 統計は保存しないので、読み込んだ側では C::default() (または QueueSeed に渡したもの)と初期状態になる。
+// 読み込むときは並び順がヒープの条件を満たしているかを確かめ、満たしていなければエラーにする。
+use crate::compare::Compare;
+use crate::FixedSizePriorityQueue;
+use core::fmt;
+use core::marker::PhantomData;
+use serde::de::{self, Deserialize, DeserializeSeed, Deserializer, SeqAccess, Visitor};
+use serde::ser::{Serialize, SerializeSeq, Serializer};
+
+impl<T, const N: usize, C, const D: usize> Serialize for FixedSizePriorityQueue<T, N, C, D>
+where
+    T: Serialize,
+    C: Compare<T>,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.size))?;
+        for item in self.iter() {
+            seq.serialize_element(item)?;
+        }
+        seq.end()
+    }
+}
+
+impl<'de, T, const N: usize, C, const D: usize> Deserialize<'de>
+    for FixedSizePriorityQueue<T, N, C, D>
+where
+    T: Deserialize<'de>,
+    C: Compare<T> + Default,
+{
+    fn deserialize<De: Deserializer<'de>>(deserializer: De) -> Result<Self, De::Error> {
+        QueueSeed::<T, N, C, D>::new(C::default()).deserialize(deserializer)
+    }
+}
+
+/// 比較関数を渡してキューを読み込むための `DeserializeSeed`
+///
+/// `ByKey` に関数ポインタを渡している場合のように、比較関数が `Default` でないときに使う。
+pub struct QueueSeed<T, const N: usize, C, const D: usize = 2> {
+    cmp: C,
+    _item: PhantomData<T>,
+}
+
+impl<T, const N: usize, C, const D: usize> QueueSeed<T, N, C, D> {
+    pub fn new(cmp: C) -> Self {
+        QueueSeed {
+            cmp,
+            _item: PhantomData,
+        }
+    }
+}
+
+impl<'de, T, const N: usize, C, const D: usize> DeserializeSeed<'de> for QueueSeed<T, N, C, D>
+where
+    T: Deserialize<'de>,
+    C: Compare<T>,
+{
+    type Value = FixedSizePriorityQueue<T, N, C, D>;
+
+    fn deserialize<De: Deserializer<'de>>(
+        self,
+        deserializer: De,
+    ) -> Result<Self::Value, De::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, T, const N: usize, C, const D: usize> Visitor<'de> for QueueSeed<T, N, C, D>
+where
+    T: Deserialize<'de>,
+    C: Compare<T>,
+{
+    type Value = FixedSizePriorityQueue<T, N, C, D>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a sequence of at most {} items in heap order", N)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut queue = FixedSizePriorityQueue::with_comparator(self.cmp);
+        while let Some(item) = seq.next_element()? {
+            if queue.size == N {
+                return Err(de::Error::invalid_length(
+                    N + 1,
+                    &"no more items than the capacity",
+                ));
+            }
+            queue.array[queue.size] = Some(item);
+            queue.size += 1;
+        }
+        queue.stats.record_len(queue.size);
+        if !queue.is_heap() {
+            return Err(de::Error::custom("items are not in heap order"));
+        }
+        Ok(queue)
+    }
+}
+
+#[cfg(feature = "postcard")]
+impl<T, const N: usize, C, const D: usize> FixedSizePriorityQueue<T, N, C, D>
+where
+    C: Compare<T>,
+{
+    // postcard の形式で buf に書き込み、書き込んだ部分を返す
+    pub fn to_postcard<'b>(&self, buf: &'b mut [u8]) -> postcard::Result<&'b mut [u8]>
+    where
+        T: Serialize,
+    {
+        postcard::to_slice(self, buf)
+    }
+
+    pub fn from_postcard<'de>(bytes: &'de [u8]) -> postcard::Result<Self>
+    where
+        T: Deserialize<'de>,
+        C: Default,
+    {
+        Self::from_postcard_with_comparator(bytes, C::default())
+    }
+
+    pub fn from_postcard_with_comparator<'de>(bytes: &'de [u8], cmp: C) -> postcard::Result<Self>
+    where
+        T: Deserialize<'de>,
+    {
+        let mut deserializer = postcard::Deserializer::from_bytes(bytes);
+        QueueSeed::new(cmp).deserialize(&mut deserializer)
+    }
+}
+
+#[cfg(all(test, feature = "postcard"))]
+#[test]
+fn test_postcard_round_trip() {
+    use crate::ByKey;
+
+    // LED のスケジュールを再起動後に復元する想定
+    #[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+    struct ScheduledCommand {
+        schedule: u64,
+        led_num: u8,
+        high: bool,
+    }
+    type BySchedule = ByKey<fn(&ScheduledCommand) -> u64>;
+    let by_schedule: BySchedule = ByKey(|c| c.schedule);
+
+    let command = |schedule, led_num, high| ScheduledCommand {
+        schedule,
+        led_num,
+        high,
+    };
+    let mut queue = FixedSizePriorityQueue::<_, 8, _>::with_comparator(by_schedule);
+    for c in [
+        command(3_000_000, 0, true),
+        command(1_500_000, 3, false),
+        command(2_000_000, 1, true),
+        command(1_000_000, 2, false),
+    ] {
+        assert!(queue.push(c).is_ok());
+    }
+
+    let mut buf = [0u8; 64];
+    let bytes = queue.to_postcard(&mut buf).unwrap();
+    let restored =
+        FixedSizePriorityQueue::<ScheduledCommand, 8, BySchedule>::from_postcard_with_comparator(
+            bytes,
+            by_schedule,
+        )
+        .unwrap();
+    assert!(restored.iter().eq(queue.iter()));
+    assert_eq!(restored.into_sorted_array(), queue.into_sorted_array());
+}
+
+#[cfg(all(test, feature = "postcard"))]
+#[test]
+fn test_postcard_rejects_invalid() {
+    let mut buf = [0u8; 32];
+    let queue = FixedSizePriorityQueue::<u32, 4>::from_array([4, 2, 3, 1]);
+    let bytes = queue.to_postcard(&mut buf).unwrap();
+    let restored = FixedSizePriorityQueue::<u32, 4>::from_postcard(bytes).unwrap();
+    assert_eq!(restored, queue);
+
+    // 入りきらない
+    assert!(FixedSizePriorityQueue::<u32, 3>::from_postcard(bytes).is_err());
+
+    // ヒープの条件を満たしていない並び
+    let bytes = postcard::to_slice(&[3u32, 1, 2][..], &mut buf).unwrap();
+    assert!(FixedSizePriorityQueue::<u32, 4>::from_postcard(bytes).is_err());
+    let bytes = postcard::to_slice(&[1u32, 3, 2][..], &mut buf).unwrap();
+    assert!(FixedSizePriorityQueue::<u32, 4>::from_postcard(bytes).is_ok());
+}
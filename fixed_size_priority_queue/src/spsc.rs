@@ -0,0 +1,317 @@
+use crate::compare::{Compare, MinOrder};
+use crate::FixedSizePriorityQueue;
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// 一つの割り込み(または片方のコア)から push し、メインループ(またはもう片方のコア)から pop する優先度付きキュー
+///
+/// push 側は固定長のリングバッファに書き込んで位置を進めるだけで、ヒープへの並べ替えは pop 側が受け取るときに行う。
+/// やりとりは読み書きの位置の atomic な load/store だけなので、どちらの操作でも割り込みを止める必要がない。
+/// (thumbv6m のように compare-and-swap を持たない環境でも使える)
+///
+/// 並び順は pop 側が受け取った要素の中で決まる。pop 側のヒープがいっぱいの間は、リングバッファに残った要素は
+/// 並べ替えの対象にならない。
+pub struct SpscPriorityQueue<T, const N: usize> {
+    buffer: [UnsafeCell<MaybeUninit<T>>; N],
+    // 読み書きの位置は 0..2N の範囲で進め、同じ位置なら空、N 離れていればいっぱい
+    head: AtomicUsize, // 次に読む位置。Consumer だけが書き換える
+    tail: AtomicUsize, // 次に書く位置。Producer だけが書き換える
+}
+
+// buffer の各要素には、head と tail で決まる範囲によって Producer と Consumer のどちらか一方しか触らない
+unsafe impl<T: Send, const N: usize> Sync for SpscPriorityQueue<T, N> {}
+
+/// `SpscPriorityQueue` に push する側
+pub struct Producer<'a, T, const N: usize> {
+    queue: &'a SpscPriorityQueue<T, N>,
+}
+
+/// `SpscPriorityQueue` から pop する側。受け取った要素をヒープに並べて持つ
+pub struct Consumer<'a, T, const N: usize, C = MinOrder> {
+    queue: &'a SpscPriorityQueue<T, N>,
+    heap: FixedSizePriorityQueue<T, N, C>,
+}
+
+impl<T, const N: usize> SpscPriorityQueue<T, N> {
+    pub const fn new() -> Self {
+        SpscPriorityQueue {
+            buffer: [const { UnsafeCell::new(MaybeUninit::uninit()) }; N],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn split<C>(&mut self) -> (Producer<'_, T, N>, Consumer<'_, T, N, C>)
+    where
+        C: Compare<T> + Default,
+    {
+        self.split_with_comparator(C::default())
+    }
+
+    pub fn split_with_comparator<C>(
+        &mut self,
+        cmp: C,
+    ) -> (Producer<'_, T, N>, Consumer<'_, T, N, C>)
+    where
+        C: Compare<T>,
+    {
+        let queue = &*self;
+        (
+            Producer { queue },
+            Consumer {
+                queue,
+                heap: FixedSizePriorityQueue::with_comparator(cmp),
+            },
+        )
+    }
+
+    fn next(i: usize) -> usize {
+        if i + 1 == 2 * N {
+            0
+        } else {
+            i + 1
+        }
+    }
+
+    fn distance(head: usize, tail: usize) -> usize {
+        if tail >= head {
+            tail - head
+        } else {
+            tail + 2 * N - head
+        }
+    }
+}
+
+impl<T, const N: usize> Default for SpscPriorityQueue<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Drop for SpscPriorityQueue<T, N> {
+    // Consumer に受け取られずにリングバッファに残っている要素を捨てる
+    fn drop(&mut self) {
+        let mut head = *self.head.get_mut();
+        let tail = *self.tail.get_mut();
+        while head != tail {
+            unsafe { self.buffer[head % N].get_mut().assume_init_drop() };
+            head = Self::next(head);
+        }
+    }
+}
+
+impl<T, const N: usize> Producer<'_, T, N> {
+    // リングバッファがいっぱいなら item をそのまま返す
+    pub fn push(&mut self, item: T) -> Result<(), T> {
+        let tail = self.queue.tail.load(Ordering::Relaxed);
+        let head = self.queue.head.load(Ordering::Acquire);
+        if SpscPriorityQueue::<T, N>::distance(head, tail) == N {
+            return Err(item);
+        }
+        unsafe { (*self.queue.buffer[tail % N].get()).write(item) };
+        self.queue
+            .tail
+            .store(SpscPriorityQueue::<T, N>::next(tail), Ordering::Release);
+        Ok(())
+    }
+
+    pub fn is_full(&self) -> bool {
+        let tail = self.queue.tail.load(Ordering::Relaxed);
+        let head = self.queue.head.load(Ordering::Acquire);
+        SpscPriorityQueue::<T, N>::distance(head, tail) == N
+    }
+}
+
+impl<T, const N: usize, C> Consumer<'_, T, N, C>
+where
+    C: Compare<T>,
+{
+    pub fn peek(&mut self) -> Option<&T> {
+        self.receive();
+        self.heap.peek()
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        self.receive();
+        self.heap.pop()
+    }
+
+    // ヒープに並んでいるものと、まだリングバッファにあるものの合計
+    pub fn len(&self) -> usize {
+        let head = self.queue.head.load(Ordering::Relaxed);
+        let tail = self.queue.tail.load(Ordering::Acquire);
+        self.heap.len() + SpscPriorityQueue::<T, N>::distance(head, tail)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // リングバッファに届いている要素を、ヒープに入るだけ受け取る
+    fn receive(&mut self) {
+        let tail = self.queue.tail.load(Ordering::Acquire);
+        let mut head = self.queue.head.load(Ordering::Relaxed);
+        while head != tail && self.heap.len() < N {
+            let item = unsafe { (*self.queue.buffer[head % N].get()).assume_init_read() };
+            // 取り出した要素はもうリングバッファのものではないので、ヒープに入れる前に head を進める。
+            // 比較が panic しても、ヒープとリングバッファの両方から同じ要素を drop しないようにする
+            head = SpscPriorityQueue::<T, N>::next(head);
+            self.queue.head.store(head, Ordering::Release);
+            if self.heap.push(item).is_err() {
+                unreachable!("heap has room");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_spsc_order() {
+    let mut queue = SpscPriorityQueue::<u32, 4>::new();
+    let (mut producer, mut consumer) = queue.split::<MinOrder>();
+    assert_eq!(consumer.pop(), None);
+
+    for item in [3, 1, 4, 2] {
+        assert!(producer.push(item).is_ok());
+    }
+    assert!(producer.is_full());
+    assert_eq!(producer.push(5), Err(5));
+    assert_eq!(consumer.len(), 4);
+
+    // 受け取った時点でリングバッファは空くので、ヒープとリングバッファの両方に持てる
+    assert_eq!(consumer.pop(), Some(1));
+    for item in [0, 5, 7, 6] {
+        assert!(producer.push(item).is_ok());
+    }
+    assert_eq!(consumer.len(), 7);
+    assert_eq!(consumer.pop(), Some(0));
+    assert_eq!(consumer.pop(), Some(2));
+    assert_eq!(consumer.pop(), Some(3));
+    assert_eq!(consumer.pop(), Some(4));
+    assert_eq!(consumer.pop(), Some(5));
+    assert_eq!(consumer.pop(), Some(6));
+    assert_eq!(consumer.pop(), Some(7));
+    assert_eq!(consumer.pop(), None);
+    assert!(consumer.is_empty());
+}
+
+#[cfg(test)]
+#[test]
+fn test_spsc_drops_remaining() {
+    use std::rc::Rc;
+
+    let counter = Rc::new(());
+    {
+        let mut queue = SpscPriorityQueue::<Rc<()>, 4>::new();
+        let (mut producer, _consumer) =
+            queue.split_with_comparator(|_: &Rc<()>, _: &Rc<()>| core::cmp::Ordering::Equal);
+        for _ in 0..3 {
+            assert!(producer.push(counter.clone()).is_ok());
+        }
+        assert_eq!(Rc::strong_count(&counter), 4);
+    }
+    assert_eq!(Rc::strong_count(&counter), 1);
+}
+
+#[cfg(test)]
+#[test]
+fn test_spsc_panicking_comparator() {
+    use std::cell::Cell;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    // 比較が panic しても、それぞれの要素を一度だけ drop する
+    struct Counted<'a>(u32, &'a Cell<u32>);
+    impl Drop for Counted<'_> {
+        fn drop(&mut self) {
+            self.1.set(self.1.get() + 1);
+        }
+    }
+
+    let drops = Cell::new(0);
+    {
+        let mut queue = SpscPriorityQueue::<Counted, 4>::new();
+        let (mut producer, mut consumer) =
+            queue.split_with_comparator(|_: &Counted, _: &Counted| -> core::cmp::Ordering {
+                panic!("comparator failed")
+            });
+        for i in 0..2 {
+            assert!(producer.push(Counted(i, &drops)).is_ok());
+        }
+        // 一つ目はそのまま入り、二つ目を並べるときに比較が panic する
+        assert!(catch_unwind(AssertUnwindSafe(|| consumer.pop().map(|c| c.0))).is_err());
+        assert_eq!(drops.get(), 0);
+    }
+    assert_eq!(drops.get(), 2);
+}
+
+#[cfg(test)]
+#[test]
+fn test_spsc_stress_threads() {
+    use std::collections::{BTreeSet, HashSet};
+    use std::sync::atomic::AtomicU32;
+
+    // 別スレッドから 8 個ごとに並びを崩した値を push し続け、取りこぼしや重複なく受け取れることと、
+    // pop する前に push し終わっていたものの中で最も小さいものより大きい値が先に出てこないことを確かめる
+    const COUNT: u32 = 200_000;
+    const N: usize = 16;
+    let value = |i: u32| i ^ 7;
+    let pushed = AtomicU32::new(0);
+    let mut queue = SpscPriorityQueue::<u32, N>::new();
+    let (mut producer, mut consumer) = queue.split::<MinOrder>();
+
+    std::thread::scope(|s| {
+        let pushed = &pushed;
+        s.spawn(move || {
+            for i in 0..COUNT {
+                let mut item = value(i);
+                while let Err(rejected) = producer.push(item) {
+                    item = rejected;
+                    std::thread::yield_now();
+                }
+                pushed.fetch_add(1, Ordering::Release);
+            }
+        });
+
+        // push し終わっていてまだ pop していない値と、push し終わる前に pop できた値
+        let mut waiting = BTreeSet::new();
+        let mut popped_early = HashSet::new();
+        let mut known = 0;
+        let mut popped = 0;
+        // 途中で assert すると push 側が待ち続けるので、最後まで受け取ってから確かめる
+        let mut out_of_order = None;
+        let mut duplicated = None;
+        while popped < COUNT {
+            let done = pushed.load(Ordering::Acquire);
+            for i in known..done {
+                if !popped_early.remove(&value(i)) {
+                    waiting.insert(value(i));
+                }
+            }
+            known = done;
+
+            match consumer.pop() {
+                Some(item) => {
+                    // ヒープに入りきる数なら、push し終わっていたものは全て並べ替えの対象になっている
+                    if waiting.len() <= N {
+                        if let Some(&smallest) = waiting.first() {
+                            if item > smallest {
+                                out_of_order.get_or_insert((item, smallest));
+                            }
+                        }
+                    }
+                    if !waiting.remove(&item) && !popped_early.insert(item) {
+                        duplicated.get_or_insert(item);
+                    }
+                    popped += 1;
+                }
+                None => std::thread::yield_now(),
+            }
+        }
+        assert_eq!(out_of_order, None, "(popped, smallest waiting)");
+        assert_eq!(duplicated, None);
+        assert_eq!(consumer.pop(), None);
+        assert!(popped_early.is_empty());
+    });
+    assert_eq!(pushed.load(Ordering::Relaxed), COUNT);
+}
@@ -0,0 +1,196 @@
+use crate::compare::{Compare, MinOrder};
+use crate::{FixedSizePriorityQueue, OverflowPolicy, QueueStats};
+use core::cmp::Ordering;
+
+/// 優先度が同じ要素は push した順に取り出される優先度付きキュー
+///
+/// 要素ごとに push した順の通し番号を付けておき、C で同じ順位になった場合は番号の小さい方を先に取り出す。
+/// 通し番号が u32 の最大値に達したら、キューに残っている要素の番号を前後関係を保ったまま 0 から振り直す。
+#[derive(Debug)]
+pub struct StablePriorityQueue<T, const N: usize, C = MinOrder> {
+    queue: FixedSizePriorityQueue<Stamped<T>, N, StableOrder<C>>,
+    next_seq: u32,
+}
+
+#[derive(Debug, PartialEq)]
+struct Stamped<T> {
+    seq: u32,
+    item: T,
+}
+
+#[derive(Debug, Default)]
+struct StableOrder<C>(C);
+
+impl<T, C> Compare<Stamped<T>> for StableOrder<C>
+where
+    C: Compare<T>,
+{
+    fn compare(&self, a: &Stamped<T>, b: &Stamped<T>) -> Ordering {
+        self.0.compare(&a.item, &b.item).then(a.seq.cmp(&b.seq))
+    }
+}
+
+impl<T, const N: usize, C> StablePriorityQueue<T, N, C>
+where
+    C: Compare<T>,
+{
+    pub fn new() -> Self
+    where
+        C: Default,
+    {
+        Self::with_comparator(C::default())
+    }
+
+    pub const fn with_comparator(cmp: C) -> Self {
+        StablePriorityQueue {
+            queue: FixedSizePriorityQueue::with_comparator(StableOrder(cmp)),
+            next_seq: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        N
+    }
+
+    // ヒープ内の並び順で要素を見る。取り出される順とは限らない
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.queue.iter().map(|s| &s.item)
+    }
+
+    pub fn peek(&self) -> Option<&T> {
+        self.queue.peek().map(|s| &s.item)
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        self.queue.pop().map(|s| s.item)
+    }
+
+    // いっぱいのときは overflow_policy に従って捨てた要素を Err で返す
+    pub fn push(&mut self, item: T) -> Result<(), T> {
+        if self.next_seq == u32::MAX {
+            self.renumber();
+        }
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.queue.push(Stamped { seq, item }).map_err(|s| s.item)
+    }
+
+    pub fn overflow_policy(&self) -> OverflowPolicy {
+        self.queue.overflow_policy()
+    }
+
+    pub fn set_overflow_policy(&mut self, overflow_policy: OverflowPolicy) {
+        self.queue.set_overflow_policy(overflow_policy)
+    }
+
+    pub fn dropped(&self) -> u32 {
+        self.queue.dropped()
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        self.queue.stats()
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.queue.reset_stats()
+    }
+
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&T) -> bool,
+    {
+        self.queue.retain(|s| f(&s.item))
+    }
+
+    pub fn remove_first<F>(&mut self, mut f: F) -> Option<T>
+    where
+        F: FnMut(&T) -> bool,
+    {
+        self.queue.remove_first(|s| f(&s.item)).map(|s| s.item)
+    }
+
+    pub fn clear(&mut self) {
+        self.queue.clear();
+        self.next_seq = 0;
+    }
+
+    // 残っている要素の通し番号を、小さい順に 0, 1, 2, ... と振り直す。
+    // 番号の前後関係は変わらないのでヒープの並びはそのままでよい
+    fn renumber(&mut self) {
+        let size = self.queue.size;
+        let mut ranks = [0u32; N];
+        for (i, rank) in ranks.iter_mut().enumerate().take(size) {
+            let seq = self.queue.item(i).seq;
+            *rank = (0..size).filter(|&j| self.queue.item(j).seq < seq).count() as u32;
+        }
+        for (i, rank) in ranks.iter().enumerate().take(size) {
+            if let Some(s) = self.queue.array[i].as_mut() {
+                s.seq = *rank;
+            }
+        }
+        self.next_seq = size as u32;
+    }
+}
+
+impl<T, const N: usize, C> Default for StablePriorityQueue<T, N, C>
+where
+    C: Compare<T> + Default,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_stable_fifo_on_ties() {
+    use crate::ByKey;
+
+    // (時刻, LED番号) を時刻だけで並べる。同じ時刻なら push した順
+    let mut queue =
+        StablePriorityQueue::<(u32, usize), 8, _>::with_comparator(ByKey(|c: &(u32, usize)| c.0));
+    for item in [(20, 3), (10, 2), (20, 0), (10, 1), (10, 3), (20, 1)] {
+        assert!(queue.push(item).is_ok());
+    }
+    assert_eq!(queue.pop(), Some((10, 2)));
+    assert_eq!(queue.pop(), Some((10, 1)));
+    assert!(queue.push((10, 0)).is_ok());
+    assert_eq!(queue.pop(), Some((10, 3)));
+    assert_eq!(queue.pop(), Some((10, 0)));
+    assert_eq!(queue.pop(), Some((20, 3)));
+    assert_eq!(queue.pop(), Some((20, 0)));
+    assert_eq!(queue.pop(), Some((20, 1)));
+    assert_eq!(queue.pop(), None);
+}
+
+#[cfg(test)]
+#[test]
+fn test_stable_fifo_across_wraparound() {
+    use crate::ByKey;
+
+    let mut queue =
+        StablePriorityQueue::<(u32, char), 4, _>::with_comparator(ByKey(|c: &(u32, char)| c.0));
+    queue.next_seq = u32::MAX - 2;
+
+    // 番号の振り直しをまたいでも、同じ優先度なら push した順
+    for item in [(1, 'a'), (2, 'x'), (1, 'b'), (1, 'c')] {
+        assert!(queue.push(item).is_ok());
+    }
+    assert_eq!(queue.pop(), Some((1, 'a')));
+    assert!(queue.push((1, 'd')).is_ok());
+    assert!(queue.next_seq < 10);
+
+    assert_eq!(queue.pop(), Some((1, 'b')));
+    assert_eq!(queue.pop(), Some((1, 'c')));
+    assert_eq!(queue.pop(), Some((1, 'd')));
+    assert_eq!(queue.pop(), Some((2, 'x')));
+    assert_eq!(queue.pop(), None);
+}
@@ -0,0 +1,139 @@
+use crate::compare::{Compare, MinOrder};
+use crate::FixedSizePriorityQueue;
+use core::cmp::Ordering;
+
+/// これまでに push されたもののうち、C で大きい方から N 個だけを残す
+///
+/// 残しているものの中で最も小さいものを先頭に置いたヒープを持ち、いっぱいのときはそれより大きいものが来た場合だけ入れ替える。
+/// ボタンを押していた時間の長いものや、割り込みの遅延の大きいものを記録しておくのに使う。
+#[derive(Debug)]
+pub struct TopK<T, const N: usize, C = MinOrder> {
+    heap: FixedSizePriorityQueue<T, N, C>,
+}
+
+impl<T, const N: usize, C> TopK<T, N, C>
+where
+    C: Compare<T>,
+{
+    pub fn new() -> Self
+    where
+        C: Default,
+    {
+        Self::with_comparator(C::default())
+    }
+
+    pub const fn with_comparator(cmp: C) -> Self {
+        TopK {
+            heap: FixedSizePriorityQueue::with_comparator(cmp),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        N
+    }
+
+    // 残しているものの中で最も小さいもの。いっぱいのときはこれより大きくないと残らない
+    pub fn min(&self) -> Option<&T> {
+        self.heap.peek()
+    }
+
+    // 順不同で見る
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.heap.iter()
+    }
+
+    // 残さなかったもの(item か、押し出された最小のもの)を Err で返す。同じ大きさなら先にあった方を残す
+    pub fn push(&mut self, item: T) -> Result<(), T> {
+        if self.heap.len() < N {
+            return self.heap.push(item);
+        }
+        let larger = self
+            .heap
+            .peek()
+            .is_some_and(|min| self.heap.cmp.compare(&item, min) == Ordering::Greater);
+        match self.heap.peek_mut() {
+            // 最小のものを書き換えて並べ直す
+            Some(mut min) if larger => Err(core::mem::replace(&mut *min, item)),
+            _ => Err(item),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.heap.clear();
+    }
+
+    // 大きい順に前から詰めた配列にする
+    pub fn into_sorted(self) -> [Option<T>; N] {
+        let len = self.heap.len();
+        let mut sorted = self.heap.into_sorted_array();
+        sorted[..len].reverse();
+        sorted
+    }
+}
+
+impl<T, const N: usize, C> Default for TopK<T, N, C>
+where
+    C: Compare<T> + Default,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_top_k() {
+    let mut top = TopK::<u32, 3>::new();
+    assert_eq!(top.min(), None);
+    assert!(top.push(5).is_ok());
+    assert!(top.push(1).is_ok());
+    assert!(top.push(3).is_ok());
+    assert_eq!(top.min(), Some(&1));
+
+    assert_eq!(top.push(0), Err(0));
+    assert_eq!(top.push(1), Err(1));
+    assert_eq!(top.push(4), Err(1));
+    assert_eq!(top.push(9), Err(3));
+    assert_eq!(top.min(), Some(&4));
+    assert_eq!(top.len(), 3);
+    assert_eq!(top.into_sorted(), [Some(9), Some(5), Some(4)]);
+
+    let mut partial = TopK::<u32, 4>::new();
+    assert!(partial.push(2).is_ok());
+    assert!(partial.push(7).is_ok());
+    assert_eq!(partial.into_sorted(), [Some(7), Some(2), None, None]);
+}
+
+#[cfg(test)]
+#[test]
+fn test_top_k_longest_holds() {
+    use crate::ByKey;
+
+    // (ボタン番号, 押していた時間[ms]) のうち、押していた時間の長いものを 3 つ残す
+    let mut longest =
+        TopK::<(usize, u32), 3, _>::with_comparator(ByKey(|hold: &(usize, u32)| hold.1));
+    for hold in [
+        (0, 120),
+        (1, 40),
+        (2, 800),
+        (0, 95),
+        (3, 310),
+        (1, 120),
+        (2, 60),
+    ] {
+        let _ = longest.push(hold);
+    }
+    assert_eq!(longest.min(), Some(&(0, 120)));
+    assert_eq!(
+        longest.into_sorted(),
+        [Some((2, 800)), Some((3, 310)), Some((0, 120))]
+    );
+}
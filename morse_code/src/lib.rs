@@ -0,0 +1,205 @@
+#![cfg_attr(not(test), no_std)]
+
+// 長さはすべて短点の長さを 1 とした単位で数える
+pub const DIT: u32 = 1;
+pub const DAH: u32 = 3;
+// 一つの文字の中の符号の間
+pub const ELEMENT_GAP: u32 = 1;
+// 文字の間
+pub const LETTER_GAP: u32 = 3;
+// 語の間
+pub const WORD_GAP: u32 = 7;
+
+/// 点灯か消灯と、それを続ける長さ(短点いくつ分か)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Element {
+    pub on: bool,
+    pub units: u32,
+}
+
+impl Element {
+    const fn off(units: u32) -> Element {
+        Element { on: false, units }
+    }
+}
+
+/// 文字列のどこまで送ったか。`next_element` に渡して次を求める
+///
+/// `element` は文字の中の位置で、偶数なら符号(点灯)、奇数ならその後の間(消灯)を表す。
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    index: usize,
+    element: usize,
+}
+
+impl Position {
+    pub const START: Position = Position::at(0);
+
+    // index 文字目の最初
+    const fn at(index: usize) -> Position {
+        Position { index, element: 0 }
+    }
+}
+
+// 短点の長さ[us]。PARIS が短点 50 個分なので、1 分 = 60_000_000us を wpm * 50 で割る
+pub fn dit_micros(wpm: u32) -> u32 {
+    1_200_000 / wpm.max(1)
+}
+
+// 文字ごとの短点(.)と長点(-)の並び。小文字は大文字と同じに扱う。送れない文字は None
+pub fn code(c: u8) -> Option<&'static [u8]> {
+    let code: &[u8] = match c.to_ascii_uppercase() {
+        b'A' => b".-",
+        b'B' => b"-...",
+        b'C' => b"-.-.",
+        b'D' => b"-..",
+        b'E' => b".",
+        b'F' => b"..-.",
+        b'G' => b"--.",
+        b'H' => b"....",
+        b'I' => b"..",
+        b'J' => b".---",
+        b'K' => b"-.-",
+        b'L' => b".-..",
+        b'M' => b"--",
+        b'N' => b"-.",
+        b'O' => b"---",
+        b'P' => b".--.",
+        b'Q' => b"--.-",
+        b'R' => b".-.",
+        b'S' => b"...",
+        b'T' => b"-",
+        b'U' => b"..-",
+        b'V' => b"...-",
+        b'W' => b".--",
+        b'X' => b"-..-",
+        b'Y' => b"-.--",
+        b'Z' => b"--..",
+        b'0' => b"-----",
+        b'1' => b".----",
+        b'2' => b"..---",
+        b'3' => b"...--",
+        b'4' => b"....-",
+        b'5' => b".....",
+        b'6' => b"-....",
+        b'7' => b"--...",
+        b'8' => b"---..",
+        b'9' => b"----.",
+        b'.' => b".-.-.-",
+        b',' => b"--..--",
+        b'?' => b"..--..",
+        b'/' => b"-..-.",
+        b'-' => b"-....-",
+        b'=' => b"-...-",
+        b':' => b"---...",
+        _ => return None,
+    };
+    Some(code)
+}
+
+/// text の position から送る点灯か消灯と、その次の位置を返す。送り終えたら None
+///
+/// 文字の最後の符号の後には文字の間を、空白には語の間になるように残りの長さを返すので、
+/// 返した順に続けると短点 1、長点 3、符号の間 1、文字の間 3、語の間 7 の長さになる。
+/// 送れない文字は飛ばす。repeat なら最後の文字の後に語の間をあけて最初に戻る。
+/// 送れる文字が一つもない場合は repeat でも None を返す。
+pub fn next_element(text: &[u8], position: Position, repeat: bool) -> Option<(Element, Position)> {
+    let Position { mut index, element } = position;
+    loop {
+        let Some(&c) = text.get(index) else {
+            // 最後の文字の後には文字の間があいているので、語の間になるように足す
+            let sendable = text.iter().any(|&c| code(c).is_some());
+            return (repeat && sendable)
+                .then_some((Element::off(WORD_GAP - LETTER_GAP), Position::START));
+        };
+        if c == b' ' {
+            return Some((Element::off(WORD_GAP - LETTER_GAP), Position::at(index + 1)));
+        }
+        let Some(code) = code(c) else {
+            index += 1;
+            continue;
+        };
+        let i = element / 2;
+        let within = Position {
+            index,
+            element: element + 1,
+        };
+        let (on, units, next) = match (element % 2, code[i]) {
+            (0, b'.') => (true, DIT, within),
+            (0, _) => (true, DAH, within),
+            _ if i + 1 < code.len() => (false, ELEMENT_GAP, within),
+            _ => (false, LETTER_GAP, Position::at(index + 1)),
+        };
+        return Some((Element { on, units }, next));
+    }
+}
+
+/// 文字列を先頭から一度だけ送るときの点灯と消灯の並び
+pub fn elements(text: &str) -> impl Iterator<Item = Element> + '_ {
+    let mut position = Some(Position::START);
+    core::iter::from_fn(move || {
+        let (element, next) = next_element(text.as_bytes(), position?, false)?;
+        position = Some(next);
+        Some(element)
+    })
+}
+
+#[cfg(test)]
+fn timings(text: &str) -> Vec<(bool, u32)> {
+    elements(text).map(|e| (e.on, e.units)).collect()
+}
+
+#[cfg(test)]
+#[test]
+fn test_gaps() {
+    // 符号の間 1、文字の間 3
+    assert_eq!(
+        timings("ET"),
+        [(true, 1), (false, 3), (true, 3), (false, 3)]
+    );
+    assert_eq!(timings("A"), [(true, 1), (false, 1), (true, 3), (false, 3)]);
+    // 語の間は文字の間 3 と空白の 4 で 7 になる
+    assert_eq!(
+        timings("E E"),
+        [(true, 1), (false, 3), (false, 4), (true, 1), (false, 3)]
+    );
+    let word_gap: u32 = timings("E E")[1..3].iter().map(|e| e.1).sum();
+    assert_eq!(word_gap, WORD_GAP);
+
+    // SOS は 短点 3、長点 3、短点 3
+    let on: Vec<u32> = elements("sos").filter(|e| e.on).map(|e| e.units).collect();
+    assert_eq!(on, [1, 1, 1, 3, 3, 3, 1, 1, 1]);
+    // PARIS は最後の語の間も含めて短点 50 個分
+    let paris: u32 = elements("PARIS").map(|e| e.units).sum::<u32>() + WORD_GAP - LETTER_GAP;
+    assert_eq!(paris, 50);
+}
+
+#[cfg(test)]
+#[test]
+fn test_unsupported_and_repeat() {
+    // 送れない文字は飛ばす
+    assert_eq!(timings("E#E"), timings("EE"));
+    assert!(timings("###").is_empty());
+    assert!(timings("").is_empty());
+
+    // 繰り返すときは最後の文字の間に 4 を足して語の間にしてから最初に戻る
+    assert_eq!(
+        next_element(b"E", Position::at(1), true),
+        Some((Element::off(4), Position::START))
+    );
+    assert_eq!(next_element(b"E", Position::at(1), false), None);
+    // 送れる文字がなければ繰り返さない
+    assert_eq!(next_element(b"###", Position::START, true), None);
+    assert_eq!(next_element(b"", Position::START, true), None);
+}
+
+#[cfg(test)]
+#[test]
+fn test_dit_micros() {
+    // 20 WPM なら短点 60ms
+    assert_eq!(dit_micros(20), 60_000);
+    assert_eq!(dit_micros(12), 100_000);
+    assert_eq!(dit_micros(1), 1_200_000);
+    // 0 は 1 WPM として扱う
+    assert_eq!(dit_micros(0), 1_200_000);
+}
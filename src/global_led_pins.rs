@@ -1,31 +1,48 @@
-use bsp::hal::fugit::ExtU32;
-use bsp::hal::{gpio, pac, pac::interrupt};
+use bsp::hal::{gpio, pac, pac::interrupt, pwm};
 use core::cell::RefCell;
 use critical_section::Mutex;
+use defmt::warn;
 
 use bsp::hal::timer::Instant;
 use core::marker::Copy;
 use core::ops::Add;
-use embedded_hal::digital::v2::OutputPin;
+use embedded_hal::PwmPin;
 use fugit::MicrosDurationU32 as Duration;
 use rp_pico as bsp;
 
-type Led0Pin = gpio::Pin<gpio::bank0::Gpio13, gpio::FunctionSioOutput, gpio::PullDown>;
-type Led1Pin = gpio::Pin<gpio::bank0::Gpio12, gpio::FunctionSioOutput, gpio::PullDown>;
-type Led2Pin = gpio::Pin<gpio::bank0::Gpio11, gpio::FunctionSioOutput, gpio::PullDown>;
-type Led3Pin = gpio::Pin<gpio::bank0::Gpio10, gpio::FunctionSioOutput, gpio::PullDown>;
+// LED のピンは PWM につないで、明るさをデューティ比で変える
+// GPIO10 と GPIO11 は PWM5 の A と B、GPIO12 と GPIO13 は PWM6 の A と B
+type Led0Pin = gpio::Pin<gpio::bank0::Gpio13, gpio::FunctionPwm, gpio::PullDown>;
+type Led1Pin = gpio::Pin<gpio::bank0::Gpio12, gpio::FunctionPwm, gpio::PullDown>;
+type Led2Pin = gpio::Pin<gpio::bank0::Gpio11, gpio::FunctionPwm, gpio::PullDown>;
+type Led3Pin = gpio::Pin<gpio::bank0::Gpio10, gpio::FunctionPwm, gpio::PullDown>;
 
-type LedPin = gpio::Pin<gpio::DynPinId, gpio::FunctionSioOutput, gpio::PullDown>;
+type Pwm5Slice = pwm::Slice<pwm::Pwm5, pwm::FreeRunning>;
+type Pwm6Slice = pwm::Slice<pwm::Pwm6, pwm::FreeRunning>;
 
 use rp_pico::hal::timer::Alarm as _;
 
-static GLOBAL_LED_PINS_COMPONENT: Mutex<RefCell<Option<LedPins>>> = Mutex::new(RefCell::new(None));
+// ピンとタイマーは init で渡されるまで無いので Option にしておく。
+// スケジュールのキューはハードウェアがなくても作れるので、static の初期化で直接作る
+struct GlobalLedPins {
+    pins: Option<LedPins>,
+    queue: LedQueue,
+}
+
+static GLOBAL_LED_PINS_COMPONENT: Mutex<RefCell<GlobalLedPins>> =
+    Mutex::new(RefCell::new(GlobalLedPins {
+        pins: None,
+        queue: FixedSizePriorityQueue::with_comparator(ByKey(ScheduledPinsCommand::schedule)),
+    }));
 
+#[allow(clippy::too_many_arguments)]
 pub fn init(
     led0: Led0Pin,
     led1: Led1Pin,
     led2: Led2Pin,
     led3: Led3Pin,
+    pwm5: Pwm5Slice,
+    pwm6: Pwm6Slice,
     timer: rp_pico::hal::Timer,
     mut alarm: rp_pico::hal::timer::Alarm1,
 ) {
@@ -33,16 +50,9 @@ pub fn init(
     alarm.enable_interrupt();
 
     critical_section::with(|cs| {
-        GLOBAL_LED_PINS_COMPONENT
-            .borrow(cs)
-            .replace(Some(LedPins::init(
-                led0.into_dyn_pin(),
-                led1.into_dyn_pin(),
-                led2.into_dyn_pin(),
-                led3.into_dyn_pin(),
-                timer,
-                alarm,
-            )))
+        GLOBAL_LED_PINS_COMPONENT.borrow(cs).borrow_mut().pins = Some(LedPins::init(
+            led0, led1, led2, led3, pwm5, pwm6, timer, alarm,
+        ))
     });
 
     unsafe {
@@ -50,53 +60,297 @@ pub fn init(
     }
 }
 
-pub fn set_led_mode(led_num: usize, led_mode: LedMode) {
+// init の前に呼ばれた場合は、呼び出す順番の誤りなので panic する
+fn with_component(f: impl FnOnce(&mut LedPins, &mut LedQueue)) {
     critical_section::with(|cs| {
-        let mut binding = GLOBAL_LED_PINS_COMPONENT.borrow(cs).borrow_mut();
-        let component = binding.as_mut().unwrap();
-        component.set_led_mode(led_num, led_mode)
+        let mut global = GLOBAL_LED_PINS_COMPONENT.borrow(cs).borrow_mut();
+        let GlobalLedPins { pins, queue } = &mut *global;
+        f(
+            pins.as_mut().expect("global_led_pins::init not called"),
+            queue,
+        )
     })
 }
 
+pub fn set_led_mode(led_num: usize, led_mode: LedMode) {
+    with_component(|component, queue| component.set_led_mode(queue, led_num, led_mode))
+}
+
 pub fn set_mode_later(led_num: usize, led_mode: LedMode, countdown: Duration) {
-    critical_section::with(|cs| {
-        let mut binding = GLOBAL_LED_PINS_COMPONENT.borrow(cs).borrow_mut();
-        let component = binding.as_mut().unwrap();
-        component.set_mode_later(led_num, led_mode, countdown)
-    })
+    with_component(|component, queue| component.set_mode_later(queue, led_num, led_mode, countdown))
+}
+
+// text をモールス信号で点滅させる。wpm は PARIS を一語とした一分間の語数。キューには一つずつ積むので待たずに戻る
+#[allow(dead_code)]
+pub fn send_morse(led_num: usize, text: &str, wpm: u32, repeat: PatternRepeat) {
+    with_component(|component, queue| component.send_morse(queue, led_num, text, wpm, repeat))
+}
+
+// キューの使われ方。キューの大きさを決めるときに見る
+#[allow(dead_code)]
+pub fn queue_stats() -> QueueStats {
+    critical_section::with(|cs| GLOBAL_LED_PINS_COMPONENT.borrow(cs).borrow().queue.stats())
 }
 
 #[interrupt]
 fn TIMER_IRQ_1() {
-    critical_section::with(|cs| {
-        let mut binding = GLOBAL_LED_PINS_COMPONENT.borrow(cs).borrow_mut();
-        let component = binding.as_mut().unwrap();
-        component.handle_schedule();
-    })
+    with_component(|component, queue| component.handle_schedule(queue))
 }
 
 //
 
-use fixed_size_priority_queue::FixedSizePriorityQueue;
+use fixed_size_priority_queue::{ByKey, FixedSizePriorityQueue, QueueStats};
 
-#[derive(Clone, Copy, Eq, PartialEq, PartialOrd, Ord)]
+// キューの中ではscheduleの順にだけ並べる
+type BySchedule = ByKey<fn(&ScheduledPinsCommand) -> Instant>;
+type LedQueue = FixedSizePriorityQueue<ScheduledPinsCommand, 20, BySchedule>;
+
+#[derive(Clone, Copy, Eq, PartialEq)]
 struct ScheduledPinsCommand {
     schedule: Instant,
     led_num: usize,
     command: Command,
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+impl ScheduledPinsCommand {
+    fn schedule(&self) -> Instant {
+        self.schedule
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
 enum Command {
     ChangeLedMode(LedMode),
-    ChangeLedStatus(LedStatus), // BLINKモードの時のみピンの変更がタイマーでくる
+    ChangeLedStatus(LedStatus, Option<u16>), // 点滅のモードの時のみピンの変更と残りの点灯回数がタイマーでくる
+    FadeStep(u8), // フェードと呼吸のモードの時のみ、明るさを一段階進めるのがタイマーでくる
+    PatternStep(usize), // パターンのモードの時のみ、次に進む位置がタイマーでくる
+    MorseStep(morse_code::Position), // モールスのモードの時のみ、次に送る位置がタイマーでくる
+}
+
+impl Command {
+    // モードを続けるためにタイマーで繰り返し積まれるものか
+    fn is_periodic(&self) -> bool {
+        matches!(
+            self,
+            Command::ChangeLedStatus(..)
+                | Command::FadeStep(_)
+                | Command::PatternStep(_)
+                | Command::MorseStep(..)
+        )
+    }
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum LedMode {
     HIGH,
     LOW,
-    BLINK,
+    BLINK, // BlinkParams::DEFAULT で点滅させる
+    #[allow(dead_code)]
+    CustomBlink(BlinkParams), // LEDごとに指定した点灯時間、消灯時間、位相、回数で点滅させる
+    #[allow(dead_code)]
+    Dim(u8), // 0(消灯)から255(HIGHと同じ)までの明るさで点けたままにする
+    #[allow(dead_code)]
+    FadeIn(Duration), // 指定した時間をかけて消灯から明るくしていき、点灯したままにする
+    #[allow(dead_code)]
+    FadeOut(Duration), // 指定した時間をかけて点灯から暗くしていき、消灯したままにする
+    #[allow(dead_code)]
+    Breathe(Duration), // 指定した時間を一周期として、明るくなって暗くなるのを繰り返す
+    #[allow(dead_code)]
+    Pattern(&'static [(LedStatus, Duration)], PatternRepeat), // 状態と続ける時間の並びの通りに点滅させる
+    Morse(MorseParams), // send_morse で渡した文字列をモールス信号で送る。send_morse からしか作れない
+}
+
+impl LedMode {
+    // 点滅のモードなら、その点滅のしかた
+    fn blink_params(&self) -> Option<BlinkParams> {
+        match *self {
+            LedMode::BLINK => Some(BlinkParams::DEFAULT),
+            LedMode::CustomBlink(params) => Some(params),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BlinkParams {
+    pub on: Duration,        // 点灯している時間
+    pub off: Duration,       // 消灯している時間
+    pub phase: Duration,     // モードを変えてから最初に点灯するまでの時間
+    pub repeat: Option<u16>, // 点灯する回数。使い切ったら消灯したままにする。None ならずっと繰り返す
+}
+
+impl BlinkParams {
+    // BLINK で使う 100ms 点灯、100ms 消灯の繰り返し
+    pub const DEFAULT: BlinkParams = BlinkParams::new(Duration::millis(100), Duration::millis(100));
+
+    pub const fn new(on: Duration, off: Duration) -> Self {
+        BlinkParams {
+            on,
+            off,
+            phase: Duration::from_ticks(0),
+            repeat: None,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub const fn with_phase(self, phase: Duration) -> Self {
+        BlinkParams { phase, ..self }
+    }
+
+    #[allow(dead_code)]
+    pub const fn with_repeat(self, repeat: u16) -> Self {
+        BlinkParams {
+            repeat: Some(repeat),
+            ..self
+        }
+    }
+}
+
+impl Default for BlinkParams {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+// モールスのモードの送り方。文字列は LedPins が LED ごとに持つので、
+// 文字列を渡さずにモードだけ切り替えられないように、フィールドは外から見えなくしておく
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MorseParams {
+    dit: Duration, // 短点の長さ
+    repeat: PatternRepeat,
+}
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PatternRepeat {
+    Loop,    // 最後まで行ったら最初から繰り返す
+    OneShot, // 最後まで行ったら消灯したままにする
+}
+
+// LedMode::Pattern に渡す点滅パターン
+#[allow(dead_code)]
+pub mod patterns {
+    use super::LedStatus::{HIGH, LOW};
+    use super::{Duration, LedStatus};
+
+    type Pattern = &'static [(LedStatus, Duration)];
+
+    const fn ms(millis: u32) -> Duration {
+        Duration::millis(millis)
+    }
+
+    // 心臓の鼓動のように二回打って休む
+    pub const HEARTBEAT: Pattern = &[
+        (HIGH, ms(100)),
+        (LOW, ms(100)),
+        (HIGH, ms(100)),
+        (LOW, ms(700)),
+    ];
+
+    // 短く二回光って休む
+    pub const DOUBLE_FLASH: Pattern = &[
+        (HIGH, ms(50)),
+        (LOW, ms(100)),
+        (HIGH, ms(50)),
+        (LOW, ms(800)),
+    ];
+
+    // ・・・－－－・・・ のあと単語の間をあける
+    pub const SOS: Pattern = &[
+        (HIGH, ms(200)),
+        (LOW, ms(200)),
+        (HIGH, ms(200)),
+        (LOW, ms(200)),
+        (HIGH, ms(200)),
+        (LOW, ms(600)),
+        (HIGH, ms(600)),
+        (LOW, ms(200)),
+        (HIGH, ms(600)),
+        (LOW, ms(200)),
+        (HIGH, ms(600)),
+        (LOW, ms(600)),
+        (HIGH, ms(200)),
+        (LOW, ms(200)),
+        (HIGH, ms(200)),
+        (LOW, ms(200)),
+        (HIGH, ms(200)),
+        (LOW, ms(1400)),
+    ];
+
+    // エラーコード n は n 回点滅してから長く消える
+    pub const ERROR_1: Pattern = &[(HIGH, ms(300)), (LOW, ms(1500))];
+    pub const ERROR_2: Pattern = &[
+        (HIGH, ms(300)),
+        (LOW, ms(300)),
+        (HIGH, ms(300)),
+        (LOW, ms(1500)),
+    ];
+    pub const ERROR_3: Pattern = &[
+        (HIGH, ms(300)),
+        (LOW, ms(300)),
+        (HIGH, ms(300)),
+        (LOW, ms(300)),
+        (HIGH, ms(300)),
+        (LOW, ms(1500)),
+    ];
+
+    // 名前からパターンを探す。コンソールなどから指定するときに使う
+    pub fn by_name(name: &str) -> Option<Pattern> {
+        match name {
+            "heartbeat" => Some(HEARTBEAT),
+            "double_flash" => Some(DOUBLE_FLASH),
+            "sos" => Some(SOS),
+            "error_1" => Some(ERROR_1),
+            "error_2" => Some(ERROR_2),
+            "error_3" => Some(ERROR_3),
+            _ => None,
+        }
+    }
+}
+
+// フェードで明るさを変える段階の数。FadeIn は 0 から FADE_STEPS まで一段階ずつ進める
+const FADE_STEPS: u8 = 32;
+
+// PWM のカウンタの最大値。出力はカウンタが比較値より小さい間だけ HIGH になるので、
+// 0xffff より一つ小さくしておき、デューティ比 u16::MAX(TOP + 1)で常に点灯するようにする
+const PWM_TOP: u16 = u16::MAX - 1;
+
+// 明るさの段階ごとのデューティ比。目に等間隔の明るさに見えるように γ=2.2 で補正してある
+const GAMMA: [u16; FADE_STEPS as usize + 1] = [
+    0, 32, 147, 359, 676, 1104, 1648, 2314, 3104, 4022, 5072, 6255, 7574, 9033, 10632, 12375,
+    14263, 16298, 18482, 20816, 23303, 25943, 28739, 31692, 34802, 38072, 41503, 45097, 48853,
+    52774, 56860, 61114, 65535,
+];
+
+// モールス信号にする文字列の長さの上限。これより後ろは送らない
+const MORSE_TEXT_LEN: usize = 32;
+
+// モールス信号で送る文字列。LEDごとに持つ
+#[derive(Clone, Copy)]
+struct MorseText {
+    bytes: [u8; MORSE_TEXT_LEN],
+    len: u8,
+}
+
+impl MorseText {
+    const EMPTY: MorseText = MorseText {
+        bytes: [0; MORSE_TEXT_LEN],
+        len: 0,
+    };
+
+    // 長すぎる場合は切り詰める
+    fn new(text: &str) -> Self {
+        let mut morse_text = Self::EMPTY;
+        for (dst, src) in morse_text.bytes.iter_mut().zip(text.bytes()) {
+            *dst = src;
+        }
+        morse_text.len = text.len().min(MORSE_TEXT_LEN) as u8;
+        morse_text
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
@@ -106,80 +360,308 @@ pub enum LedStatus {
 }
 
 pub struct LedPins {
-    leds: [LedPin; 4],
+    pwm5: Pwm5Slice,
+    pwm6: Pwm6Slice,
+    // PWM につないだピンは持っておくだけ
+    _leds: (Led0Pin, Led1Pin, Led2Pin, Led3Pin),
     led_modes: [LedMode; 4],
-    queue: FixedSizePriorityQueue<ScheduledPinsCommand, 20>,
+    morse_texts: [MorseText; 4],
     timer: rp_pico::hal::Timer,
     alarm: rp_pico::hal::timer::Alarm1,
 }
 
 impl LedPins {
-    fn blink_millis() -> Duration {
-        100.millis()
-    }
-
+    #[allow(clippy::too_many_arguments)]
     pub fn init(
-        led0: LedPin,
-        led1: LedPin,
-        led2: LedPin,
-        led3: LedPin,
+        led0: Led0Pin,
+        led1: Led1Pin,
+        led2: Led2Pin,
+        led3: Led3Pin,
+        mut pwm5: Pwm5Slice,
+        mut pwm6: Pwm6Slice,
         timer: rp_pico::hal::Timer,
         alarm: rp_pico::hal::timer::Alarm1,
     ) -> Self {
-        LedPins {
-            leds: [led0, led1, led2, led3],
+        let leds = (
+            pwm6.channel_b.output_to(led0),
+            pwm6.channel_a.output_to(led1),
+            pwm5.channel_b.output_to(led2),
+            pwm5.channel_a.output_to(led3),
+        );
+        // 125MHz / 65535 で約 1.9kHz なのでちらつきは見えない
+        pwm5.set_top(PWM_TOP);
+        pwm6.set_top(PWM_TOP);
+        pwm5.enable();
+        pwm6.enable();
+
+        let mut led_pins = LedPins {
+            pwm5,
+            pwm6,
+            _leds: leds,
             led_modes: [LedMode::LOW; 4],
-            queue: FixedSizePriorityQueue::new(),
+            morse_texts: [MorseText::EMPTY; 4],
             timer,
             alarm,
+        };
+        for led_num in 0..4 {
+            led_pins._set_duty(led_num, 0);
+        }
+        led_pins
+    }
+
+    // 明るさ(0..=255)をデューティ比にする。255 で常に点灯。GAMMA の段階の間は線形に補間する
+    fn _level_to_duty(level: u8) -> u16 {
+        let position = level as u32 * FADE_STEPS as u32;
+        let (i, frac) = ((position / 255) as usize, position % 255);
+        match GAMMA.get(i + 1) {
+            Some(&upper) => {
+                let lower = GAMMA[i] as u32;
+                (lower + (upper as u32 - lower) * frac / 255) as u16
+            }
+            None => GAMMA[i],
         }
     }
 
-    pub fn set_led_mode(&mut self, led_num: usize, led_mode: LedMode) {
-        if led_num > 4 {
+    // フェードと呼吸のモードで、段階 step の明るさにして次の段階をスケジュールする。最後の段階なら None を返す
+    fn _fade(&mut self, led_num: usize, mode: LedMode, step: u8) -> Option<ScheduledPinsCommand> {
+        let (level, next_step, interval) = match mode {
+            LedMode::FadeIn(period) => (
+                step,
+                (step < FADE_STEPS).then_some(step + 1),
+                period / FADE_STEPS as u32,
+            ),
+            LedMode::FadeOut(period) => (
+                FADE_STEPS - step,
+                (step < FADE_STEPS).then_some(step + 1),
+                period / FADE_STEPS as u32,
+            ),
+            // 明るくなる FADE_STEPS 段階と暗くなる FADE_STEPS 段階を繰り返す
+            LedMode::Breathe(period) => (
+                if step <= FADE_STEPS {
+                    step
+                } else {
+                    2 * FADE_STEPS - step
+                },
+                Some((step + 1) % (2 * FADE_STEPS)),
+                period / (2 * FADE_STEPS as u32),
+            ),
+            _ => return None,
+        };
+        self._set_duty(led_num, GAMMA[level as usize]);
+        next_step.map(|next_step| ScheduledPinsCommand {
+            schedule: self.timer.get_counter().add(interval),
+            led_num,
+            command: Command::FadeStep(next_step),
+        })
+    }
+
+    // パターンの index 番目の状態にして、次に進む時刻をスケジュールする。一度だけの場合は最後まで行ったら消灯して None を返す
+    fn _pattern(
+        &mut self,
+        led_num: usize,
+        steps: &'static [(LedStatus, Duration)],
+        repeat: PatternRepeat,
+        index: usize,
+    ) -> Option<ScheduledPinsCommand> {
+        let index = match (index < steps.len(), repeat) {
+            (true, _) => index,
+            (false, PatternRepeat::Loop) if !steps.is_empty() => 0,
+            (false, _) => {
+                self._set_status(led_num, LedStatus::LOW);
+                return None;
+            }
+        };
+        let (status, duration) = steps[index];
+        self._set_status(led_num, status);
+        Some(ScheduledPinsCommand {
+            schedule: self.timer.get_counter().add(duration),
+            led_num,
+            command: Command::PatternStep(index + 1),
+        })
+    }
+
+    // 点滅で status にして、反対の状態にする時刻をスケジュールする。remaining は残りの点灯回数で、使い切ったら消灯して None を返す
+    fn _blink(
+        &mut self,
+        led_num: usize,
+        params: BlinkParams,
+        status: LedStatus,
+        remaining: Option<u16>,
+    ) -> Option<ScheduledPinsCommand> {
+        let (next, wait, remaining) = match (status, remaining) {
+            (LedStatus::HIGH, Some(0)) => {
+                self._set_status(led_num, LedStatus::LOW);
+                return None;
+            }
+            (LedStatus::HIGH, _) => (LedStatus::LOW, params.on, remaining.map(|r| r - 1)),
+            (LedStatus::LOW, _) => (LedStatus::HIGH, params.off, remaining),
+        };
+        self._set_status(led_num, status);
+        Some(ScheduledPinsCommand {
+            schedule: self.timer.get_counter().add(wait),
+            led_num,
+            command: Command::ChangeLedStatus(next, remaining),
+        })
+    }
+
+    // モールス信号の position から一つ分点灯か消灯をして、次の位置をスケジュールする。送り終えたら消灯して None を返す
+    fn _morse(
+        &mut self,
+        led_num: usize,
+        params: MorseParams,
+        position: morse_code::Position,
+    ) -> Option<ScheduledPinsCommand> {
+        let text = self.morse_texts[led_num];
+        let repeat = params.repeat == PatternRepeat::Loop;
+        let Some((element, next)) = morse_code::next_element(text.as_bytes(), position, repeat)
+        else {
+            self._set_status(led_num, LedStatus::LOW);
+            return None;
+        };
+        let status = if element.on {
+            LedStatus::HIGH
+        } else {
+            LedStatus::LOW
+        };
+        self._set_status(led_num, status);
+        Some(ScheduledPinsCommand {
+            schedule: self.timer.get_counter().add(params.dit * element.units),
+            led_num,
+            command: Command::MorseStep(next),
+        })
+    }
+
+    // 前のモードで積まれていた切り替えは、モードが変わると不要になるので取り除く
+    fn _remove_periodic(queue: &mut LedQueue, led_num: usize) {
+        queue.retain(|c| !(c.led_num == led_num && c.command.is_periodic()));
+    }
+
+    fn _set_duty(&mut self, led_num: usize, duty: u16) {
+        match led_num {
+            0 => self.pwm6.channel_b.set_duty(duty),
+            1 => self.pwm6.channel_a.set_duty(duty),
+            2 => self.pwm5.channel_b.set_duty(duty),
+            3 => self.pwm5.channel_a.set_duty(duty),
+            _ => panic!("invalid led_num: {}", led_num),
+        }
+    }
+
+    fn _set_status(&mut self, led_num: usize, status: LedStatus) {
+        match status {
+            LedStatus::HIGH => self._set_duty(led_num, u16::MAX),
+            LedStatus::LOW => self._set_duty(led_num, 0),
+        }
+    }
+
+    fn set_led_mode(&mut self, queue: &mut LedQueue, led_num: usize, led_mode: LedMode) {
+        if led_num >= 4 {
             panic!("invalid led_num: {}", led_num);
         }
+        Self::_remove_periodic(queue, led_num);
         if let Some(next) = self._change_mode(led_num, led_mode) {
-            self.queue.push(next);
-            if self.alarm.finished() {
-                self.alarm.schedule_at(next.schedule).unwrap();
-            }
+            Self::_push_command(queue, next);
+            self._schedule_alarm(queue);
         }
     }
 
-    pub fn set_mode_later(&mut self, led_num: usize, led_mode: LedMode, countdown: Duration) {
-        if led_num > 4 {
+    fn send_morse(
+        &mut self,
+        queue: &mut LedQueue,
+        led_num: usize,
+        text: &str,
+        wpm: u32,
+        repeat: PatternRepeat,
+    ) {
+        if led_num >= 4 {
             panic!("invalid led_num: {}", led_num);
         }
+        if text.len() > MORSE_TEXT_LEN {
+            warn!(
+                "morse text is too long. only the first {} bytes are sent",
+                MORSE_TEXT_LEN
+            );
+        }
+        self.morse_texts[led_num] = MorseText::new(text);
+        let params = MorseParams {
+            dit: Duration::micros(morse_code::dit_micros(wpm)),
+            repeat,
+        };
+        self.set_led_mode(queue, led_num, LedMode::Morse(params));
+    }
 
-        self.queue.push(ScheduledPinsCommand {
-            schedule: self.timer.get_counter().add(countdown),
-            led_num,
-            command: Command::ChangeLedMode(led_mode),
-        });
-        if self.alarm.finished() {
-            self.alarm.schedule(countdown).unwrap();
+    fn set_mode_later(
+        &mut self,
+        queue: &mut LedQueue,
+        led_num: usize,
+        led_mode: LedMode,
+        countdown: Duration,
+    ) {
+        if led_num >= 4 {
+            panic!("invalid led_num: {}", led_num);
         }
+
+        Self::_push_command(
+            queue,
+            ScheduledPinsCommand {
+                schedule: self.timer.get_counter().add(countdown),
+                led_num,
+                command: Command::ChangeLedMode(led_mode),
+            },
+        );
+        self._schedule_alarm(queue);
     }
 
-    // モード切り替え HIGHとLOWは即座にピンの状態を変えるが、BLINKの場合次に動かすコマンドを返す
+    // キューの先頭の時刻にタイマーをセットし直す。
+    // セット済みの時刻より前のコマンドが積まれた場合も、それまで待たせないように毎回先頭に合わせる
+    fn _schedule_alarm(&mut self, queue: &LedQueue) {
+        if let Some(next) = queue.peek() {
+            self.alarm.schedule_at(next.schedule).unwrap();
+        }
+    }
+
+    // キューがいっぱいで捨てられたコマンドがあればログに残す
+    fn _push_command(queue: &mut LedQueue, command: ScheduledPinsCommand) {
+        if let Err(dropped) = queue.push(command) {
+            warn!(
+                "led queue is full. dropped a command for led {}: {}",
+                dropped.led_num,
+                queue.stats()
+            );
+        }
+    }
+
+    // モード切り替え HIGHとLOWとDimは即座にピンの状態を変えるが、点滅やフェードの場合次に動かすコマンドを返す
     fn _change_mode(&mut self, led_num: usize, led_mode: LedMode) -> Option<ScheduledPinsCommand> {
         self.led_modes[led_num] = led_mode;
         match led_mode {
             LedMode::HIGH => {
-                self.leds[led_num].set_high().unwrap();
+                self._set_status(led_num, LedStatus::HIGH);
                 None
             }
             LedMode::LOW => {
-                self.leds[led_num].set_low().unwrap();
+                self._set_status(led_num, LedStatus::LOW);
+                None
+            }
+            LedMode::Dim(level) => {
+                self._set_duty(led_num, Self::_level_to_duty(level));
                 None
             }
-            LedMode::BLINK => {
-                self.leds[led_num].set_high().unwrap();
+            LedMode::FadeIn(_) | LedMode::FadeOut(_) | LedMode::Breathe(_) => {
+                self._fade(led_num, led_mode, 0)
+            }
+            LedMode::Pattern(steps, repeat) => self._pattern(led_num, steps, repeat, 0),
+            LedMode::Morse(params) => self._morse(led_num, params, morse_code::Position::START),
+            LedMode::BLINK | LedMode::CustomBlink(_) => {
+                let params = led_mode.blink_params()?;
+                if params.phase.ticks() == 0 {
+                    return self._blink(led_num, params, LedStatus::HIGH, params.repeat);
+                }
+                // 位相の分だけ消灯して待ってから点滅を始める
+                self._set_status(led_num, LedStatus::LOW);
                 Some(ScheduledPinsCommand {
-                    schedule: self.timer.get_counter().add(Self::blink_millis()),
+                    schedule: self.timer.get_counter().add(params.phase),
                     led_num,
-                    command: Command::ChangeLedStatus(LedStatus::LOW),
+                    command: Command::ChangeLedStatus(LedStatus::HIGH, params.repeat),
                 })
             }
         }
@@ -193,53 +675,55 @@ impl LedPins {
     ) -> Option<ScheduledPinsCommand> {
         let current_mode = self.led_modes[led_num];
         match (command, current_mode) {
-            (Command::ChangeLedMode(mode), _) => {
-                self._change_mode(led_num, mode);
-                None
+            (Command::ChangeLedMode(mode), _) => self._change_mode(led_num, mode),
+            // ピン切り替えは点滅のモードの時だけ扱う
+            (Command::ChangeLedStatus(pin, remaining), mode) => {
+                let params = mode.blink_params()?;
+                self._blink(led_num, params, pin, remaining)
             }
-            // ピン切り替えはBLINKの時だけ扱う
-            (Command::ChangeLedStatus(pin), LedMode::BLINK) => {
-                if pin == LedStatus::HIGH {
-                    self.leds[led_num].set_high().unwrap();
-                    Some(ScheduledPinsCommand {
-                        schedule: self.timer.get_counter().add(Self::blink_millis()),
-                        led_num,
-                        command: Command::ChangeLedStatus(LedStatus::LOW),
-                    })
-                } else {
-                    self.leds[led_num].set_low().unwrap();
-                    Some(ScheduledPinsCommand {
-                        schedule: self.timer.get_counter().add(Self::blink_millis()),
-                        led_num,
-                        command: Command::ChangeLedStatus(LedStatus::HIGH),
-                    })
-                }
+            (Command::FadeStep(step), mode) => self._fade(led_num, mode, step),
+            (Command::PatternStep(index), LedMode::Pattern(steps, repeat)) => {
+                self._pattern(led_num, steps, repeat, index)
+            }
+            (Command::PatternStep(_), _) => None,
+            (Command::MorseStep(position), LedMode::Morse(params)) => {
+                self._morse(led_num, params, position)
             }
-            (Command::ChangeLedStatus(_), _) => None,
+            (Command::MorseStep(..), _) => None,
         }
     }
 
-    pub fn handle_schedule(&mut self) {
+    fn handle_schedule(&mut self, queue: &mut LedQueue) {
         self.alarm.clear_interrupt();
         let now = self.timer.get_counter();
 
         // キューに溜まったもののうち現在より前のものは全て実行
-        while let Some(&next) = self.queue.peek() {
+        while let Some(&next) = queue.peek() {
             if next.schedule <= now {
-                let _ = self.queue.pop();
-                if let Some(next) = self._handle_command(next.led_num, next.command) {
-                    self.queue.push(next);
+                let following = self._handle_command(next.led_num, next.command);
+                match (next.command, following) {
+                    (Command::ChangeLedMode(_), following) => {
+                        let _ = queue.pop();
+                        Self::_remove_periodic(queue, next.led_num);
+                        if let Some(following) = following {
+                            Self::_push_command(queue, following);
+                        }
+                    }
+                    // 次のピン切り替えは先頭を書き換えて並べ直す(pop と push で二回並べ替えない)
+                    (_, Some(following)) => {
+                        if let Some(mut head) = queue.peek_mut() {
+                            *head = following;
+                        }
+                    }
+                    (_, None) => {
+                        let _ = queue.pop();
+                    }
                 }
             } else {
                 break;
             }
         }
 
-        // タイマーが完了していて、キューに残りがあれば、タイマーセット
-        if self.alarm.finished() {
-            if let Some(next) = self.queue.peek() {
-                self.alarm.schedule_at(next.schedule).unwrap();
-            }
-        }
+        self._schedule_alarm(queue);
     }
 }
@@ -116,11 +116,15 @@ fn main() -> ! {
         timer.alarm_0().unwrap(),
     );
 
+    // LED は PWM の PWM5 と PWM6 で明るさを変える
+    let pwm_slices = bsp::hal::pwm::Slices::new(pac.PWM, &mut pac.RESETS);
     global_led_pins::init(
-        pins.gpio13.into_push_pull_output(),
-        pins.gpio12.into_push_pull_output(),
-        pins.gpio11.into_push_pull_output(),
-        pins.gpio10.into_push_pull_output(),
+        pins.gpio13.reconfigure(),
+        pins.gpio12.reconfigure(),
+        pins.gpio11.reconfigure(),
+        pins.gpio10.reconfigure(),
+        pwm_slices.pwm5,
+        pwm_slices.pwm6,
         timer,
         timer.alarm_1().unwrap(),
     );
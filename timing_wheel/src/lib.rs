@@ -0,0 +1,442 @@
+#![cfg_attr(not(test), no_std)]
+
+// 1段あたり 64 slot を 6段重ねる。段 l の 1 slot は 64^l tick を表す
+const SLOT_BITS: u32 = 6;
+const SLOTS: usize = 1 << SLOT_BITS;
+const LEVELS: usize = 6;
+// 一番上の段で表せる範囲。これより先の期限は一番上の段に入れておき、回ってきたときに入れ直す
+const MAX_RANGE: u64 = 1 << (SLOT_BITS as usize * LEVELS);
+const NIL: u16 = u16::MAX;
+
+/// 階層型のタイミングホイール
+///
+/// 期限(tick)ごとに段と slot を決めて双方向リストにつなぐので、登録と取り消しは O(1)。
+/// `advance_to` で時刻を進めると、期限を過ぎた要素を期限の順に取り出せる。
+/// 上の段の slot に回ってきた要素は、残り時間に合わせて下の段に入れ直される。
+///
+/// tick の単位は使う側で決める。rp2040 の Timer なら `Instant::ticks()` (マイクロ秒)をそのまま渡すか、
+/// ミリ秒に丸めて渡す。
+#[derive(Debug)]
+pub struct TimingWheel<T, const N: usize> {
+    nodes: [Node<T>; N],
+    heads: [[u16; SLOTS]; LEVELS],
+    // 段ごとに、要素の入っている slot のビットを立てておく
+    occupied: [u64; LEVELS],
+    // 期限を過ぎていて、取り出されるのを待っている要素(先に入ったものから取り出す)
+    pending_head: u16,
+    pending_tail: u16,
+    free: u16,
+    now: u64,
+    len: usize,
+}
+
+#[derive(Debug)]
+struct Node<T> {
+    item: Option<T>,
+    deadline: u64,
+    // 取り出されるたびに増やし、古いハンドルを無効にする
+    generation: u32,
+    list: List,
+    prev: u16,
+    next: u16,
+}
+
+// 要素がどのリストにつながっているか
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum List {
+    Free,
+    Pending,
+    Slot { level: u8, slot: u8 },
+}
+
+/// `TimingWheel::insert` で返される、登録した要素を指すハンドル
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimerHandle {
+    index: u16,
+    generation: u32,
+}
+
+impl<T, const N: usize> TimingWheel<T, N> {
+    pub fn new() -> Self {
+        const { assert!(N < NIL as usize, "capacity must be less than u16::MAX") };
+        TimingWheel {
+            nodes: core::array::from_fn(|i| Node {
+                item: None,
+                deadline: 0,
+                generation: 0,
+                list: List::Free,
+                prev: NIL,
+                next: if i + 1 < N { (i + 1) as u16 } else { NIL },
+            }),
+            heads: [[NIL; SLOTS]; LEVELS],
+            occupied: [0; LEVELS],
+            pending_head: NIL,
+            pending_tail: NIL,
+            free: if N > 0 { 0 } else { NIL },
+            now: 0,
+            len: 0,
+        }
+    }
+
+    // 最後に advance_to で進めた時刻
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        N
+    }
+
+    // deadline (tick) に期限が来る要素を登録する。いっぱいなら item をそのまま返す。
+    // すでに過ぎている期限なら、次の advance_to ですぐに取り出される
+    pub fn insert(&mut self, deadline: u64, item: T) -> Result<TimerHandle, T> {
+        if self.free == NIL {
+            return Err(item);
+        }
+        let i = self.free;
+        let node = self.node(i);
+        let next_free = node.next;
+        node.item = Some(item);
+        node.deadline = deadline;
+        let generation = node.generation;
+        self.free = next_free;
+        self.len += 1;
+        self.place(i);
+        Ok(TimerHandle {
+            index: i,
+            generation,
+        })
+    }
+
+    // まだ取り出されていなければ取り消して返す
+    pub fn cancel(&mut self, handle: TimerHandle) -> Option<T> {
+        let i = self.index_of(handle)?;
+        self.unlink(i);
+        Some(self.release(i))
+    }
+
+    pub fn deadline(&self, handle: TimerHandle) -> Option<u64> {
+        self.index_of(handle)
+            .map(|i| self.nodes[i as usize].deadline)
+    }
+
+    pub fn contains(&self, handle: TimerHandle) -> bool {
+        self.index_of(handle).is_some()
+    }
+
+    // 次に advance_to を呼ぶべき時刻。一番早い期限か、それより前に上の段から入れ直しが必要な時刻
+    pub fn next_wakeup(&self) -> Option<u64> {
+        if self.pending_head != NIL {
+            Some(self.now)
+        } else {
+            self.next_expiration().map(|(_, _, start)| start)
+        }
+    }
+
+    // now まで時刻を進め、期限を過ぎた要素を期限の順に取り出すイテレータを返す。
+    // 途中で捨てた場合、残りは次の advance_to で取り出される
+    pub fn advance_to(&mut self, now: u64) -> Expired<'_, T, N> {
+        Expired {
+            wheel: self,
+            target: now,
+        }
+    }
+
+    fn node(&mut self, i: u16) -> &mut Node<T> {
+        &mut self.nodes[i as usize]
+    }
+
+    fn index_of(&self, handle: TimerHandle) -> Option<u16> {
+        let node = self.nodes.get(handle.index as usize)?;
+        if node.item.is_some() && node.generation == handle.generation {
+            Some(handle.index)
+        } else {
+            None
+        }
+    }
+
+    // 期限を過ぎていれば pending の末尾に、そうでなければ期限に合った段と slot の先頭につなぐ
+    fn place(&mut self, i: u16) {
+        let deadline = self.nodes[i as usize].deadline;
+        if deadline <= self.now {
+            let tail = self.pending_tail;
+            let node = self.node(i);
+            node.list = List::Pending;
+            node.prev = tail;
+            node.next = NIL;
+            if tail != NIL {
+                self.node(tail).next = i;
+            } else {
+                self.pending_head = i;
+            }
+            self.pending_tail = i;
+        } else {
+            let level = Self::level_for(self.now, deadline);
+            let slot = ((deadline >> (level as u32 * SLOT_BITS)) % SLOTS as u64) as usize;
+            let head = self.heads[level][slot];
+            let node = self.node(i);
+            node.list = List::Slot {
+                level: level as u8,
+                slot: slot as u8,
+            };
+            node.prev = NIL;
+            node.next = head;
+            if head != NIL {
+                self.node(head).prev = i;
+            }
+            self.heads[level][slot] = i;
+            self.occupied[level] |= 1 << slot;
+        }
+    }
+
+    // 今の時刻と期限とで、上から見て初めて 6bit の区切りが食い違う段に入れる
+    fn level_for(now: u64, deadline: u64) -> usize {
+        let mut masked = (now ^ deadline) | (SLOTS as u64 - 1);
+        if masked >= MAX_RANGE {
+            masked = MAX_RANGE - 1;
+        }
+        let significant = 63 - masked.leading_zeros();
+        (significant / SLOT_BITS) as usize
+    }
+
+    // 次に中身を見るべき (段, slot, その slot が始まる時刻)。下の段の要素ほど先に期限が来る
+    fn next_expiration(&self) -> Option<(usize, usize, u64)> {
+        (0..LEVELS).find_map(|level| {
+            let occupied = self.occupied[level];
+            if occupied == 0 {
+                return None;
+            }
+            let slot_range = 1u64 << (level as u32 * SLOT_BITS);
+            let level_range = slot_range << SLOT_BITS;
+            let now_slot = ((self.now / slot_range) % SLOTS as u64) as u32;
+            // 今の slot に入っているのは一周先の要素なので、他に入っている slot があればそちらを先に見る
+            let mut rotated = occupied.rotate_right(now_slot);
+            if rotated != 1 {
+                rotated &= !1;
+            }
+            let slot = (rotated.trailing_zeros() + now_slot) as usize % SLOTS;
+            let level_start = self.now & !(level_range - 1);
+            let mut start = level_start + slot as u64 * slot_range;
+            if start <= self.now {
+                // 今の slot より前にある slot は一周先
+                start += level_range;
+            }
+            Some((level, slot, start))
+        })
+    }
+
+    fn unlink(&mut self, i: u16) {
+        let Node {
+            prev, next, list, ..
+        } = self.nodes[i as usize];
+        if prev != NIL {
+            self.node(prev).next = next;
+        } else {
+            match list {
+                List::Pending => self.pending_head = next,
+                List::Slot { level, slot } => {
+                    let (level, slot) = (level as usize, slot as usize);
+                    self.heads[level][slot] = next;
+                    if next == NIL {
+                        self.occupied[level] &= !(1 << slot);
+                    }
+                }
+                List::Free => unreachable!("free node is not linked"),
+            }
+        }
+        if next != NIL {
+            self.node(next).prev = prev;
+        } else if list == List::Pending {
+            self.pending_tail = prev;
+        }
+    }
+
+    // リストから外した要素を取り出し、空きに戻す
+    fn release(&mut self, i: u16) -> T {
+        let free = self.free;
+        let node = self.node(i);
+        let item = node.item.take().unwrap();
+        node.generation = node.generation.wrapping_add(1);
+        node.list = List::Free;
+        node.prev = NIL;
+        node.next = free;
+        self.free = i;
+        self.len -= 1;
+        item
+    }
+
+    fn pop_pending(&mut self) -> Option<T> {
+        let i = self.pending_head;
+        if i == NIL {
+            return None;
+        }
+        self.unlink(i);
+        Some(self.release(i))
+    }
+}
+
+impl<T, const N: usize> Default for TimingWheel<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// `TimingWheel::advance_to` で返される、期限を過ぎた要素を取り出すイテレータ
+pub struct Expired<'a, T, const N: usize> {
+    wheel: &'a mut TimingWheel<T, N>,
+    target: u64,
+}
+
+impl<T, const N: usize> Iterator for Expired<'_, T, N> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        let wheel = &mut *self.wheel;
+        loop {
+            if let Some(item) = wheel.pop_pending() {
+                return Some(item);
+            }
+            match wheel.next_expiration() {
+                Some((level, slot, start)) if start <= self.target => {
+                    // slot の始まりまで時刻を進め、中身を期限が来たものは pending に、それ以外は下の段に入れ直す
+                    wheel.now = start;
+                    let mut i = wheel.heads[level][slot];
+                    wheel.heads[level][slot] = NIL;
+                    wheel.occupied[level] &= !(1 << slot);
+                    while i != NIL {
+                        let next = wheel.nodes[i as usize].next;
+                        wheel.place(i);
+                        i = next;
+                    }
+                }
+                _ => {
+                    wheel.now = wheel.now.max(self.target);
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_insert_cancel_advance() {
+    let mut wheel = TimingWheel::<&str, 8>::new();
+    let a = wheel.insert(100, "a").unwrap();
+    let b = wheel.insert(30, "b").unwrap();
+    let c = wheel.insert(5_000, "c").unwrap();
+    let d = wheel.insert(30, "d").unwrap();
+    assert_eq!(wheel.len(), 4);
+    assert_eq!(wheel.deadline(c), Some(5_000));
+
+    assert_eq!(wheel.advance_to(29).count(), 0);
+    assert_eq!(wheel.now(), 29);
+    let expired: Vec<_> = wheel.advance_to(100).collect();
+    assert_eq!(expired.len(), 3);
+    assert!(expired[..2].contains(&"b") && expired[..2].contains(&"d"));
+    assert_eq!(expired[2], "a");
+    assert!(!wheel.contains(a) && !wheel.contains(b) && !wheel.contains(d));
+
+    // 取り消したものは出てこない。取り出し済みのハンドルでは取り消せない
+    assert_eq!(wheel.cancel(c), Some("c"));
+    assert_eq!(wheel.cancel(c), None);
+    assert_eq!(wheel.cancel(a), None);
+    assert_eq!(wheel.advance_to(10_000).count(), 0);
+    assert!(wheel.is_empty());
+
+    // 過ぎた期限は次の advance_to ですぐに出てくる
+    wheel.insert(1, "late").unwrap();
+    assert_eq!(wheel.next_wakeup(), Some(10_000));
+    assert_eq!(wheel.advance_to(10_000).collect::<Vec<_>>(), vec!["late"]);
+}
+
+#[cfg(test)]
+#[test]
+fn test_full_and_far_future() {
+    let mut wheel = TimingWheel::<u64, 2>::new();
+    // 一番上の段でも表せない先の期限
+    let far = MAX_RANGE * 3 + 12_345;
+    wheel.insert(far, far).unwrap();
+    wheel.insert(7, 7).unwrap();
+    assert_eq!(wheel.insert(8, 8), Err(8));
+
+    assert_eq!(wheel.advance_to(MAX_RANGE).collect::<Vec<_>>(), vec![7]);
+    assert_eq!(wheel.advance_to(far - 1).count(), 0);
+    assert_eq!(wheel.advance_to(far).collect::<Vec<_>>(), vec![far]);
+    assert_eq!(wheel.next_wakeup(), None);
+}
+
+#[cfg(test)]
+#[test]
+fn test_matches_brute_force() {
+    // 乱数で登録・取り消し・時刻を進めることを繰り返し、素朴な実装と取り出される要素と順序が一致するか確認する
+    const N: usize = 64;
+    let mut seed = 42u64;
+    let mut rand = move || {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        seed
+    };
+
+    let mut wheel = TimingWheel::<(u64, u32), N>::new();
+    let mut expected: Vec<(TimerHandle, u64, u32)> = Vec::new();
+    let mut id = 0;
+    for _ in 0..20_000 {
+        match rand() % 10 {
+            0..=4 => {
+                let delay = match rand() % 4 {
+                    0 => rand() % 64,
+                    1 => rand() % 5_000,
+                    2 => rand() % 1_000_000,
+                    _ => rand() % (MAX_RANGE * 2),
+                };
+                let deadline = wheel.now() + delay;
+                id += 1;
+                match wheel.insert(deadline, (deadline, id)) {
+                    Ok(handle) => expected.push((handle, deadline, id)),
+                    Err(_) => assert_eq!(expected.len(), N),
+                }
+            }
+            5 => {
+                if !expected.is_empty() {
+                    let (handle, deadline, id) =
+                        expected.swap_remove((rand() % expected.len() as u64) as usize);
+                    assert_eq!(wheel.cancel(handle), Some((deadline, id)));
+                }
+            }
+            _ => {
+                let step = if rand() % 20 == 0 {
+                    rand() % (MAX_RANGE * 2)
+                } else {
+                    rand() % 3_000
+                };
+                let target = wheel.now() + step;
+                let expired: Vec<_> = wheel.advance_to(target).collect();
+                assert!(expired.windows(2).all(|w| w[0].0 <= w[1].0));
+                let mut due: Vec<_> = expected
+                    .iter()
+                    .filter(|e| e.1 <= target)
+                    .map(|e| (e.1, e.2))
+                    .collect();
+                expected.retain(|e| e.1 > target);
+                let mut sorted = expired.clone();
+                sorted.sort();
+                due.sort();
+                assert_eq!(sorted, due);
+                assert_eq!(wheel.len(), expected.len());
+                if let Some(earliest) = expected.iter().map(|e| e.1).min() {
+                    assert!(wheel.next_wakeup().unwrap() <= earliest);
+                }
+            }
+        }
+    }
+}